@@ -0,0 +1,950 @@
+use lsp_types::{
+    Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, DiagnosticTag, Location,
+    NumberOrString, Position, Range, Url,
+};
+use std::collections::{HashMap, HashSet};
+
+use crate::config::Config;
+use crate::document::DocumentIndex;
+use crate::stack;
+
+/// A single instruction or label definition, split the same way `stacky` parses a line.
+///
+/// `stacky::Script` doesn't expose the program it parses, so editor features scan the source
+/// with this instead; keep it in step with the parser's own line handling.
+pub struct Instruction<'a> {
+    /// zero-based line number
+    pub line: usize,
+    /// byte column of `name` within the line
+    pub col: usize,
+    /// command name, or `name:` for a label definition
+    pub name: &'a str,
+    /// remainder of the line after the command, trimmed
+    pub arg: &'a str,
+    /// byte column of `arg` within the line
+    pub arg_col: usize,
+}
+
+impl<'a> Instruction<'a> {
+    pub fn label(&self) -> Option<&'a str> {
+        self.name.strip_suffix(':')
+    }
+
+    /// Range of the label name, without the trailing `:`.
+    pub fn name_range(&self) -> Range {
+        line_range(
+            self.line,
+            self.col,
+            self.col + self.name.trim_end_matches(':').len(),
+        )
+    }
+
+    pub fn arg_range(&self) -> Range {
+        line_range(self.line, self.arg_col, self.arg_col + self.arg.len())
+    }
+
+    /// Values given inline, as in `add 1 2`, with their byte columns. Splits on spaces outside
+    /// of quotes like `stacky` does. Commands whose argument names a variable, label or type,
+    /// or counts values as `pop` does, have none.
+    pub fn values(&self) -> Vec<(usize, &'a str)> {
+        if self.label().is_some()
+            || matches!(
+                self.name,
+                "goto" | "br" | "store" | "load" | "convert" | "pop"
+            )
+        {
+            return Vec::new();
+        }
+        let mut values = Vec::new();
+        let mut start = None;
+        let mut in_quotes = false;
+        for (offset, c) in self.arg.char_indices() {
+            if c == '"' {
+                in_quotes = !in_quotes;
+            }
+            if c == ' ' && !in_quotes {
+                if let Some(begin) = start.take() {
+                    values.push((self.arg_col + begin, &self.arg[begin..offset]));
+                }
+            } else if start.is_none() {
+                start = Some(offset);
+            }
+        }
+        if let Some(begin) = start {
+            values.push((self.arg_col + begin, &self.arg[begin..]));
+        }
+        values
+    }
+
+    pub fn range(&self) -> Range {
+        let end = if self.arg.is_empty() {
+            self.col + self.name.len()
+        } else {
+            self.arg_col + self.arg.len()
+        };
+        line_range(self.line, self.col, end)
+    }
+}
+
+pub fn line_range(line: usize, start: usize, end: usize) -> Range {
+    Range {
+        start: Position {
+            line: line as u32,
+            character: start as u32,
+        },
+        end: Position {
+            line: line as u32,
+            character: end as u32,
+        },
+    }
+}
+
+/// The part of a line before its comment. Like `stacky`, this cuts at the first `;` even
+/// inside a string literal, so `print "a;b"` only ever sees `print "a`.
+pub fn strip_comment(line: &str) -> &str {
+    line.split(';').next().unwrap_or("")
+}
+
+pub fn instructions(text: &str) -> Vec<Instruction<'_>> {
+    let mut result = Vec::new();
+    for (line, raw) in text.lines().enumerate() {
+        let code = strip_comment(raw);
+        let trimmed = code.trim_start();
+        if trimmed.trim_end().is_empty() {
+            continue;
+        }
+        let col = code.len() - trimmed.len();
+        let (name, rest) = trimmed
+            .trim_end()
+            .split_once(' ')
+            .unwrap_or((trimmed.trim_end(), ""));
+        let arg = rest.trim();
+        let arg_col = if arg.is_empty() {
+            col + name.len()
+        } else {
+            col + name.len() + 1 + (rest.len() - rest.trim_start().len())
+        };
+        result.push(Instruction {
+            line,
+            col,
+            name,
+            arg,
+            arg_col,
+        });
+    }
+    result
+}
+
+/// Source of the lints, so clients can tell them apart from the parser's own errors,
+/// which keep the plain `stacky` source.
+pub const LINT_SOURCE: &str = "stacky-lint";
+
+/// Diagnostic code of a pair of adjacent instructions that cancel each other out.
+pub const REDUNDANT_PAIR: &str = "redundant-pair";
+
+/// Diagnostic code of a `load` of a variable no `store` in the document sets. The diagnostic's
+/// data holds the variable name.
+pub const UNSTORED_VARIABLE: &str = "unstored-variable";
+
+fn lint(range: Range, severity: DiagnosticSeverity, message: String) -> Diagnostic {
+    Diagnostic {
+        range,
+        severity: Some(severity),
+        source: Some(LINT_SOURCE.to_string()),
+        message,
+        ..Default::default()
+    }
+}
+
+/// Runs the line-based lints that complement the parser's own errors.
+pub fn lint_document(uri: &Url, text: &str, config: &Config) -> Vec<Diagnostic> {
+    let instructions = instructions(text);
+    let mut diagnostics = Vec::new();
+    lint_self_loops(&instructions, &mut diagnostics);
+    lint_label_variable_collisions(uri, &instructions, &mut diagnostics);
+    lint_duplicate_labels(uri, &instructions, &mut diagnostics);
+    lint_unjumped_labels(&instructions, &mut diagnostics);
+    lint_unreachable_labels(&instructions, &mut diagnostics);
+    lint_division_by_zero(&instructions, &mut diagnostics);
+    lint_integer_overflow(&instructions, &mut diagnostics);
+    lint_trailing_words(&instructions, &mut diagnostics);
+    lint_redundant_pairs(&instructions, &mut diagnostics);
+    lint_unstored_loads(&instructions, &mut diagnostics);
+    lint_undefined_labels(&instructions, &mut diagnostics);
+    lint_overwritten_stores(uri, &instructions, &mut diagnostics);
+    lint_unloaded_variables(&instructions, &mut diagnostics);
+    lint_string_escapes(&instructions, &mut diagnostics);
+    lint_label_lines(&instructions, &mut diagnostics);
+    lint_disabled_commands(&instructions, config, &mut diagnostics);
+    if config.heuristic_lints {
+        lint_branch_conditions(&instructions, &mut diagnostics);
+        lint_assert_conditions(&instructions, &mut diagnostics);
+    }
+    if config.require_exit {
+        lint_missing_exit(&instructions, &mut diagnostics);
+    }
+    diagnostics
+}
+
+/// Parses an integer literal the way `stacky` does, including `0x` and `0b` prefixes.
+pub fn parse_integer(s: &str) -> Option<i64> {
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = s.strip_prefix("0b").or_else(|| s.strip_prefix("0B")) {
+        i64::from_str_radix(bin, 2).ok()
+    } else {
+        s.parse::<i64>().ok()
+    }
+}
+
+/// Flags a name operand with more words after it, as in `goto done print`. `stacky` allows one
+/// instruction per line and takes the whole rest of the line as the name, so the extra words
+/// are not run and the jump or variable silently refers to a name like `done print`. A label
+/// can't have such a name, so that is an error; a variable can, and a `store a b` with a
+/// matching `load a b` works, so only a name with no counterpart gets a warning.
+fn lint_trailing_words(instructions: &[Instruction], diagnostics: &mut Vec<Diagnostic>) {
+    let names = |command: &str| -> HashSet<&str> {
+        instructions
+            .iter()
+            .filter(|inst| inst.name == command)
+            .map(|inst| inst.arg)
+            .collect()
+    };
+    let (stored, loaded) = (names("store"), names("load"));
+    for inst in instructions {
+        let (kind, severity) = match inst.name {
+            "goto" | "br" => ("label", DiagnosticSeverity::ERROR),
+            "store" if !loaded.contains(inst.arg) => ("variable", DiagnosticSeverity::WARNING),
+            "load" if !stored.contains(inst.arg) => ("variable", DiagnosticSeverity::WARNING),
+            _ => continue,
+        };
+        let Some(space) = inst.arg.find(char::is_whitespace) else {
+            continue;
+        };
+        let extra = inst.arg[space..].trim_start();
+        let start = inst.arg_col + inst.arg.len() - extra.len();
+        diagnostics.push(lint(
+            line_range(inst.line, start, inst.arg_col + inst.arg.len()),
+            severity,
+            format!(
+                "only one instruction is allowed per line; `{}` reads the rest of the line as \
+                 the {} name `{}`",
+                inst.name, kind, inst.arg
+            ),
+        ));
+    }
+}
+
+/// Flags decimal integer literals too large for a 64-bit integer, which `stacky` silently
+/// reads as floats instead.
+fn lint_integer_overflow(instructions: &[Instruction], diagnostics: &mut Vec<Diagnostic>) {
+    for inst in instructions {
+        for (col, value) in inst.values() {
+            let digits = value.strip_prefix('-').unwrap_or(value);
+            if digits.is_empty()
+                || !digits.bytes().all(|b| b.is_ascii_digit())
+                || parse_integer(value).is_some()
+            {
+                continue;
+            }
+            diagnostics.push(lint(
+                line_range(inst.line, col, col + value.len()),
+                DiagnosticSeverity::ERROR,
+                format!(
+                    "integer literal `{}` does not fit in a 64-bit integer and would be read as a float",
+                    value
+                ),
+            ));
+        }
+    }
+}
+
+/// Flags a `div` or `mod` whose divisor is an integer zero pushed right before it, or given
+/// inline as in `div 1 0`.
+fn lint_division_by_zero(instructions: &[Instruction], diagnostics: &mut Vec<Diagnostic>) {
+    for (i, inst) in instructions.iter().enumerate() {
+        if !matches!(inst.name, "div" | "mod") {
+            continue;
+        }
+        let divisor = if inst.arg.is_empty() {
+            match i.checked_sub(1).map(|p| &instructions[p]) {
+                Some(prev) if prev.name == "push" => prev.arg.split_whitespace().last(),
+                _ => None,
+            }
+        } else {
+            inst.arg.split_whitespace().nth(1)
+        };
+        if divisor.and_then(parse_integer) == Some(0) {
+            diagnostics.push(lint(
+                inst.range(),
+                DiagnosticSeverity::ERROR,
+                if inst.name == "div" {
+                    "division by zero".to_string()
+                } else {
+                    "modulo by zero".to_string()
+                },
+            ));
+        }
+    }
+}
+
+/// Hints at adjacent instructions that cancel each other out, such as `dup` or a single
+/// `push` immediately followed by `pop`.
+fn lint_redundant_pairs(instructions: &[Instruction], diagnostics: &mut Vec<Diagnostic>) {
+    for pair in instructions.windows(2) {
+        let (first, second) = (&pair[0], &pair[1]);
+        let pushes_one = match first.name {
+            "dup" => first.arg.is_empty(),
+            "push" => !first.arg.is_empty() && !first.arg.contains(char::is_whitespace),
+            _ => false,
+        };
+        if !pushes_one || second.name != "pop" || !matches!(second.arg, "" | "1") {
+            continue;
+        }
+        let mut diagnostic = lint(
+            Range {
+                start: first.range().start,
+                end: second.range().end,
+            },
+            DiagnosticSeverity::HINT,
+            format!(
+                "`{}` followed by `pop` has no effect; consider removing both",
+                first.name
+            ),
+        );
+        diagnostic.code = Some(NumberOrString::String(REDUNDANT_PAIR.to_string()));
+        diagnostic.tags = Some(vec![DiagnosticTag::UNNECESSARY]);
+        diagnostics.push(diagnostic);
+    }
+}
+
+/// Flags a `load` of a variable no `store` can have set by then, which fails at runtime with
+/// "variable not found". Usually one side of a rename was missed. A variable stored nowhere
+/// at all is a warning where the `load` can't run and an error otherwise; one stored only
+/// elsewhere is an error when no path to the `load` passes one of its stores.
+fn lint_unstored_loads(instructions: &[Instruction], diagnostics: &mut Vec<Diagnostic>) {
+    let stored: HashSet<&str> = instructions
+        .iter()
+        .filter(|inst| inst.name == "store")
+        .map(|inst| inst.arg)
+        .collect();
+    // variables some path to each instruction has stored, or `None` where no path gets to
+    let maybe_stored = stack::flow(
+        instructions,
+        HashSet::new(),
+        |inst, before: &HashSet<String>| {
+            let mut after = before.clone();
+            if inst.name == "store" {
+                after.insert(inst.arg.to_string());
+            }
+            after
+        },
+        |a, b| a.union(b).cloned().collect(),
+    );
+    for (inst, before) in instructions.iter().zip(maybe_stored) {
+        if inst.name != "load" || inst.arg.is_empty() {
+            continue;
+        }
+        let (severity, message) = match before {
+            Some(before) if before.contains(inst.arg) => continue,
+            _ if !stored.contains(inst.arg) => (
+                if before.is_some() {
+                    DiagnosticSeverity::ERROR
+                } else {
+                    DiagnosticSeverity::WARNING
+                },
+                format!("variable `{}` is never stored", inst.arg),
+            ),
+            Some(_) => (
+                DiagnosticSeverity::ERROR,
+                format!(
+                    "variable `{}` is not stored on any path to this load",
+                    inst.arg
+                ),
+            ),
+            None => continue,
+        };
+        let mut diagnostic = lint(inst.arg_range(), severity, message);
+        diagnostic.code = Some(NumberOrString::String(UNSTORED_VARIABLE.to_string()));
+        diagnostic.data = Some(serde_json::Value::String(inst.arg.to_string()));
+        diagnostics.push(diagnostic);
+    }
+}
+
+/// Flags a `goto` or `br` to a label the document never defines. `stacky` parses it fine and
+/// only fails with "label not found" once the jump is taken.
+fn lint_undefined_labels(instructions: &[Instruction], diagnostics: &mut Vec<Diagnostic>) {
+    let targets = label_targets(instructions);
+    for inst in instructions {
+        // a name with a space in it is `lint_trailing_words`'s to explain
+        if !matches!(inst.name, "goto" | "br")
+            || inst.arg.is_empty()
+            || inst.arg.contains(' ')
+            || targets.contains_key(inst.arg)
+        {
+            continue;
+        }
+        diagnostics.push(lint(
+            inst.arg_range(),
+            DiagnosticSeverity::ERROR,
+            format!("label `{}` is never defined", inst.arg),
+        ));
+    }
+}
+
+/// Warns about backslashes in string literals. `stacky` keeps the text between the quotes as
+/// is, so `"a\n"` holds a backslash and an `n`, not a newline.
+fn lint_string_escapes(instructions: &[Instruction], diagnostics: &mut Vec<Diagnostic>) {
+    for inst in instructions {
+        for (col, value) in inst.values() {
+            if !value.starts_with('"') {
+                continue;
+            }
+            let mut chars = value.char_indices();
+            while let Some((offset, c)) = chars.next() {
+                if c != '\\' {
+                    continue;
+                }
+                // taking the next character along makes `\\` one warning, not two
+                let Some((_, next)) = chars.next() else {
+                    continue;
+                };
+                let start = col + offset;
+                diagnostics.push(lint(
+                    line_range(inst.line, start, start + 1 + next.len_utf8()),
+                    DiagnosticSeverity::WARNING,
+                    format!(
+                        "strings have no escape sequences; this is a backslash followed by `{}`",
+                        next
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+/// Flags anything after a label definition on the same line. The parser silently drops it,
+/// so `loop: push 1` would never push.
+fn lint_label_lines(instructions: &[Instruction], diagnostics: &mut Vec<Diagnostic>) {
+    for inst in instructions {
+        if inst.label().is_none() || inst.arg.is_empty() {
+            continue;
+        }
+        let first = inst.arg.split_whitespace().next().unwrap_or("");
+        let message = if first.ends_with(':') {
+            "only one label can be defined per line"
+        } else {
+            "a label must be on its own line; the rest of this line is ignored"
+        };
+        diagnostics.push(lint(
+            inst.arg_range(),
+            DiagnosticSeverity::ERROR,
+            message.to_string(),
+        ));
+    }
+}
+
+/// Warns about commands the configured target doesn't support.
+fn lint_disabled_commands(
+    instructions: &[Instruction],
+    config: &Config,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    for inst in instructions {
+        if inst.label().is_some() || !config.is_disabled(inst.name) {
+            continue;
+        }
+        diagnostics.push(lint(
+            inst.name_range(),
+            DiagnosticSeverity::WARNING,
+            format!("`{}` is not supported in this target", inst.name),
+        ));
+    }
+}
+
+/// Whether execution can continue past the instruction onto the next line.
+pub fn falls_through(inst: &Instruction) -> bool {
+    !matches!(inst.name, "goto" | "exit" | "error")
+}
+
+/// Maps each label to the index of its definition. Like `stacky`, a label defined twice
+/// resolves to its last definition.
+pub fn label_targets<'a>(instructions: &[Instruction<'a>]) -> HashMap<&'a str, usize> {
+    instructions
+        .iter()
+        .enumerate()
+        .filter_map(|(i, inst)| inst.label().map(|label| (label, i)))
+        .collect()
+}
+
+/// Indices of the instructions that can run right after `instructions[i]`.
+pub fn successors(
+    instructions: &[Instruction],
+    targets: &HashMap<&str, usize>,
+    i: usize,
+) -> Vec<usize> {
+    let inst = &instructions[i];
+    let mut next = Vec::new();
+    if falls_through(inst) && i + 1 < instructions.len() {
+        next.push(i + 1);
+    }
+    if matches!(inst.name, "goto" | "br") {
+        next.extend(targets.get(inst.arg));
+    }
+    next
+}
+
+/// Grays out labels that no `goto` or `br` targets but that are still reached by falling
+/// through from the previous line. A label before the first instruction is the program entry
+/// and is left alone, as is one in dead code, which `lint_unreachable_labels` reports.
+fn lint_unjumped_labels(instructions: &[Instruction], diagnostics: &mut Vec<Diagnostic>) {
+    let reached = reachable(instructions);
+    for (i, inst) in instructions.iter().enumerate() {
+        let Some(label) = inst.label() else {
+            continue;
+        };
+        if !reached[i] {
+            continue;
+        }
+        let jumped = instructions
+            .iter()
+            .any(|other| matches!(other.name, "goto" | "br") && other.arg == label);
+        if jumped || instructions[..i].iter().all(|prev| prev.label().is_some()) {
+            continue;
+        }
+        // a label nothing falls into either is reported by `lint_unreachable_labels`
+        if falls_through(&instructions[i - 1]) {
+            let mut diagnostic = lint(
+                inst.name_range(),
+                DiagnosticSeverity::HINT,
+                format!(
+                    "label `{}` is never jumped to; it is only reached by falling through",
+                    label
+                ),
+            );
+            diagnostic.tags = Some(vec![DiagnosticTag::UNNECESSARY]);
+            diagnostics.push(diagnostic);
+        }
+    }
+}
+
+/// Which instructions some path from the first one reaches, following jumps and fallthrough.
+pub fn reachable(instructions: &[Instruction]) -> Vec<bool> {
+    let targets = label_targets(instructions);
+    let mut reached = vec![false; instructions.len()];
+    let mut pending = Vec::new();
+    if !instructions.is_empty() {
+        reached[0] = true;
+        pending.push(0);
+    }
+    while let Some(i) = pending.pop() {
+        for next in successors(instructions, &targets, i) {
+            if !reached[next] {
+                reached[next] = true;
+                pending.push(next);
+            }
+        }
+    }
+    reached
+}
+
+/// Warns about label sections no path from the program entry reaches, even through other
+/// jumps, since everything in them is dead code.
+fn lint_unreachable_labels(instructions: &[Instruction], diagnostics: &mut Vec<Diagnostic>) {
+    let reached = reachable(instructions);
+    for (inst, reached) in instructions.iter().zip(reached) {
+        let Some(label) = inst.label() else {
+            continue;
+        };
+        if reached {
+            continue;
+        }
+        let mut diagnostic = lint(
+            inst.name_range(),
+            DiagnosticSeverity::WARNING,
+            format!("label `{}` is unreachable from the program entry", label),
+        );
+        diagnostic.tags = Some(vec![DiagnosticTag::UNNECESSARY]);
+        diagnostics.push(diagnostic);
+    }
+}
+
+/// Hints at the last instruction when running off its end is a path the program can take,
+/// for codebases that want every program to finish with an explicit `exit`.
+fn lint_missing_exit(instructions: &[Instruction], diagnostics: &mut Vec<Diagnostic>) {
+    let Some(last) = instructions.last() else {
+        return;
+    };
+    if !falls_through(last) || !reachable(instructions).last().copied().unwrap_or(false) {
+        return;
+    }
+    diagnostics.push(lint(
+        last.range(),
+        DiagnosticSeverity::HINT,
+        "the program can run off its end; finish it with `exit`".to_string(),
+    ));
+}
+
+/// Whether the instruction leaves a boolean on top of the stack.
+fn produces_boolean(inst: &Instruction) -> bool {
+    match inst.name {
+        "gt" | "lt" | "ge" | "le" | "eq" | "ne" | "and" | "or" | "not" | "xor" => true,
+        "push" => matches!(inst.arg.split_whitespace().last(), Some("true" | "false")),
+        _ => false,
+    }
+}
+
+/// Flags a `br` whose condition does not come straight from a comparison, logical op or
+/// boolean literal.
+fn lint_branch_conditions(instructions: &[Instruction], diagnostics: &mut Vec<Diagnostic>) {
+    for pair in instructions.windows(2) {
+        let (prev, inst) = (&pair[0], &pair[1]);
+        if inst.name != "br" || prev.label().is_some() || produces_boolean(prev) {
+            continue;
+        }
+        diagnostics.push(lint(
+            inst.range(),
+            DiagnosticSeverity::INFORMATION,
+            format!(
+                "`br` condition comes from `{}`, which may not be a boolean",
+                prev.name
+            ),
+        ));
+    }
+}
+
+/// Flags an `assert` whose condition does not come straight from a comparison, logical op or
+/// boolean literal. A message pushed separately right before the `assert` is looked past.
+fn lint_assert_conditions(instructions: &[Instruction], diagnostics: &mut Vec<Diagnostic>) {
+    for (i, inst) in instructions.iter().enumerate() {
+        if inst.name != "assert" {
+            continue;
+        }
+        let mut feeder = i.checked_sub(1);
+        if let Some(p) = feeder {
+            let prev = &instructions[p];
+            let message = prev.name == "push"
+                && matches!(prev.values().as_slice(), [(_, value)] if value.starts_with('"'));
+            if inst.arg.is_empty() && message {
+                feeder = p.checked_sub(1);
+            }
+        }
+        let Some(prev) = feeder.map(|p| &instructions[p]) else {
+            continue;
+        };
+        if prev.label().is_some() || produces_boolean(prev) {
+            continue;
+        }
+        diagnostics.push(lint(
+            inst.range(),
+            DiagnosticSeverity::INFORMATION,
+            format!(
+                "`assert` condition comes from `{}`, which may not be a boolean",
+                prev.name
+            ),
+        ));
+    }
+}
+
+/// Flags a label section that can only leave by an unconditional `goto` back to itself.
+/// Any `br`, `exit`, `error`, `assert`, jump elsewhere or fallthrough before that
+/// `goto` is treated as a possible way out.
+fn lint_self_loops(instructions: &[Instruction], diagnostics: &mut Vec<Diagnostic>) {
+    let mut current: Option<&str> = None;
+    for inst in instructions {
+        if let Some(label) = inst.label() {
+            current = Some(label);
+            continue;
+        }
+        let Some(label) = current else {
+            continue;
+        };
+        match inst.name {
+            "br" | "exit" | "error" | "assert" => current = None,
+            "goto" => {
+                if inst.arg == label {
+                    diagnostics.push(lint(
+                        inst.range(),
+                        DiagnosticSeverity::WARNING,
+                        format!(
+                            "infinite loop: `{}` jumps back to itself with no exit",
+                            label
+                        ),
+                    ));
+                }
+                current = None;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Flags each definition of a label after its first. `stacky` accepts them and sends every
+/// jump to the last one, which makes the earlier ones easy to misread as jump targets.
+fn lint_duplicate_labels(
+    uri: &Url,
+    instructions: &[Instruction],
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let mut first: HashMap<&str, Range> = HashMap::new();
+    for inst in instructions {
+        let Some(label) = inst.label() else {
+            continue;
+        };
+        let Some(first_range) = first.get(label) else {
+            first.insert(label, inst.name_range());
+            continue;
+        };
+        let mut diagnostic = lint(
+            inst.name_range(),
+            DiagnosticSeverity::WARNING,
+            format!(
+                "label `{}` is already defined; jumps to it go to its last definition",
+                label
+            ),
+        );
+        diagnostic.related_information = Some(vec![DiagnosticRelatedInformation {
+            location: Location {
+                uri: uri.clone(),
+                range: *first_range,
+            },
+            message: format!("label `{}` first defined here", label),
+        }]);
+        diagnostics.push(diagnostic);
+    }
+}
+
+/// Flags the first `store` of a variable that shares its name with a label.
+fn lint_label_variable_collisions(
+    uri: &Url,
+    instructions: &[Instruction],
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let mut labels = HashMap::new();
+    for inst in instructions {
+        if let Some(label) = inst.label() {
+            labels.entry(label).or_insert_with(|| inst.name_range());
+        }
+    }
+
+    let mut reported = Vec::new();
+    for inst in instructions {
+        if inst.name != "store" || reported.contains(&inst.arg) {
+            continue;
+        }
+        let Some(label_range) = labels.get(inst.arg) else {
+            continue;
+        };
+        reported.push(inst.arg);
+        let mut diagnostic = lint(
+            inst.arg_range(),
+            DiagnosticSeverity::WARNING,
+            format!("variable `{}` has the same name as a label", inst.arg),
+        );
+        diagnostic.related_information = Some(vec![DiagnosticRelatedInformation {
+            location: Location {
+                uri: uri.clone(),
+                range: *label_range,
+            },
+            message: format!("label `{}` defined here", inst.arg),
+        }]);
+        diagnostics.push(diagnostic);
+    }
+}
+
+fn loaded_variables<'a>(instructions: &[Instruction<'a>]) -> HashSet<&'a str> {
+    instructions
+        .iter()
+        .filter(|inst| inst.name == "load")
+        .map(|inst| inst.arg)
+        .collect()
+}
+
+/// Grays out every `store` of a variable that no `load` reads. The value still has to leave
+/// the stack, so the store can become a `pop` rather than go away.
+fn lint_unloaded_variables(instructions: &[Instruction], diagnostics: &mut Vec<Diagnostic>) {
+    let loaded = loaded_variables(instructions);
+    for inst in instructions {
+        if inst.name != "store" || inst.arg.is_empty() || loaded.contains(inst.arg) {
+            continue;
+        }
+        let mut diagnostic = lint(
+            inst.range(),
+            DiagnosticSeverity::HINT,
+            format!("variable `{}` is stored but never loaded", inst.arg),
+        );
+        diagnostic.tags = Some(vec![DiagnosticTag::UNNECESSARY]);
+        diagnostics.push(diagnostic);
+    }
+}
+
+/// Flags a `store` that is overwritten by another before any `load` reads it. Only straight
+/// runs of code are considered: a label or jump forgets every pending store, since another
+/// path may load the variable in between.
+fn lint_overwritten_stores(
+    uri: &Url,
+    instructions: &[Instruction],
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let loaded = loaded_variables(instructions);
+    let mut pending: HashMap<&str, &Instruction> = HashMap::new();
+    for inst in instructions {
+        if inst.label().is_some() || matches!(inst.name, "goto" | "br") {
+            pending.clear();
+            continue;
+        }
+        match inst.name {
+            "load" => {
+                pending.remove(inst.arg);
+            }
+            // a variable that is never loaded at all is `lint_unloaded_variables`'s to report
+            "store" if !inst.arg.is_empty() && loaded.contains(inst.arg) => {
+                let Some(previous) = pending.insert(inst.arg, inst) else {
+                    continue;
+                };
+                let mut diagnostic = lint(
+                    previous.range(),
+                    DiagnosticSeverity::WARNING,
+                    format!(
+                        "`{}` is stored again before it is loaded, so this value is never used",
+                        inst.arg
+                    ),
+                );
+                diagnostic.tags = Some(vec![DiagnosticTag::UNNECESSARY]);
+                diagnostic.related_information = Some(vec![DiagnosticRelatedInformation {
+                    location: Location {
+                        uri: uri.clone(),
+                        range: inst.range(),
+                    },
+                    message: format!("`{}` overwritten here", inst.arg),
+                }]);
+                diagnostics.push(diagnostic);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RenameError {
+    /// the position is not on a label or variable
+    NoSymbol,
+    InvalidName(String),
+    /// another symbol of the same kind already uses the new name
+    Collision(String),
+}
+
+impl std::fmt::Display for RenameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenameError::NoSymbol => write!(f, "no label or variable at this position"),
+            RenameError::InvalidName(name) => write!(f, "`{}` is not a valid name", name),
+            RenameError::Collision(name) => write!(f, "`{}` is already defined", name),
+        }
+    }
+}
+
+/// Computes the edits renaming the label or variable under `position` to `new_name`.
+pub fn rename_edits(
+    index: &DocumentIndex,
+    position: Position,
+    new_name: &str,
+) -> Result<Vec<(Range, String)>, RenameError> {
+    let (kind, name, _) = index.symbol_at(position).ok_or(RenameError::NoSymbol)?;
+    let invalid = |c: char| c.is_whitespace() || matches!(c, ';' | ':' | '"');
+    if new_name.is_empty() || new_name.contains(invalid) {
+        return Err(RenameError::InvalidName(new_name.to_string()));
+    }
+    if new_name != name && !index.occurrences(kind, new_name).is_empty() {
+        return Err(RenameError::Collision(new_name.to_string()));
+    }
+    Ok(index
+        .occurrences(kind, name)
+        .into_iter()
+        .map(|range| (range, new_name.to_string()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: &str =
+        "main:\n    push 1\n    store count\n    load count\n    goto main\nend:\n";
+
+    fn at(line: u32, character: u32) -> Position {
+        Position { line, character }
+    }
+
+    /// Lines and start columns of the edits, each of which must insert `new_name`.
+    fn edited(edits: Vec<(Range, String)>, new_name: &str) -> Vec<(u32, u32)> {
+        edits
+            .into_iter()
+            .map(|(range, text)| {
+                assert_eq!(text, new_name);
+                (range.start.line, range.start.character)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rename_label() {
+        let index = DocumentIndex::new(PROGRAM);
+        let edits = rename_edits(&index, at(4, 10), "start").unwrap();
+        assert_eq!(edited(edits, "start"), vec![(0, 0), (4, 9)]);
+    }
+
+    #[test]
+    fn rename_variable() {
+        let index = DocumentIndex::new(PROGRAM);
+        let edits = rename_edits(&index, at(2, 12), "total").unwrap();
+        assert_eq!(edited(edits, "total"), vec![(2, 10), (3, 9)]);
+    }
+
+    #[test]
+    fn rename_onto_existing_label() {
+        let index = DocumentIndex::new(PROGRAM);
+        assert_eq!(
+            rename_edits(&index, at(0, 1), "end"),
+            Err(RenameError::Collision("end".to_string()))
+        );
+    }
+
+    #[test]
+    fn rename_off_symbol() {
+        let index = DocumentIndex::new(PROGRAM);
+        assert_eq!(
+            rename_edits(&index, at(1, 5), "x"),
+            Err(RenameError::NoSymbol)
+        );
+    }
+
+    #[test]
+    fn jumps_in_comments_ignored() {
+        let text = "main:\n    ; goto foo\n    push 1 ; goto foo\n    exit\n";
+        let index = DocumentIndex::new(text);
+        assert_eq!(
+            index.references(crate::document::SymbolKind::Label, "foo", true),
+            Vec::new()
+        );
+
+        let mut diagnostics = Vec::new();
+        lint_undefined_labels(&instructions(text), &mut diagnostics);
+        assert_eq!(diagnostics, Vec::new());
+    }
+
+    #[test]
+    fn unreachable_label_not_reported_as_fallen_into() {
+        let uri = Url::parse("file:///test/dead.stacky").unwrap();
+        let text = "main:\n    exit 0\ndead:\n    nop\nalso:\n    nop\n";
+        let messages: Vec<String> = lint_document(&uri, text, &Config::default())
+            .into_iter()
+            .filter(|d| d.range.start.line == 4)
+            .map(|d| d.message)
+            .collect();
+        assert_eq!(
+            messages,
+            vec!["label `also` is unreachable from the program entry".to_string()]
+        );
+    }
+}
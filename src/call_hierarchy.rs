@@ -0,0 +1,123 @@
+use lsp_types::{
+    CallHierarchyIncomingCall, CallHierarchyItem, CallHierarchyOutgoingCall, Position, Range,
+    SymbolKind, Url,
+};
+
+use crate::analysis;
+use crate::document::{self, Document};
+use crate::position;
+
+/// A label's block: its definition and the instructions up to the next label. Label A calls
+/// label B when A's block has a `goto B` or `br B`.
+struct Block {
+    label: String,
+    /// the label name, without the `:`
+    name_range: Range,
+    /// from the label to the last instruction before the next one
+    range: Range,
+    /// `goto` and `br` targets with the range of their operand
+    jumps: Vec<(String, Range)>,
+}
+
+/// Blocks in document order. Instructions above the first label belong to no block.
+fn blocks(text: &str) -> Vec<Block> {
+    let mut blocks: Vec<Block> = Vec::new();
+    for inst in analysis::instructions(text) {
+        if let Some(label) = inst.label() {
+            blocks.push(Block {
+                label: label.to_string(),
+                name_range: inst.name_range(),
+                range: inst.range(),
+                jumps: Vec::new(),
+            });
+            continue;
+        }
+        let Some(block) = blocks.last_mut() else {
+            continue;
+        };
+        block.range.end = inst.range().end;
+        if matches!(inst.name, "goto" | "br") && !inst.arg.is_empty() {
+            block.jumps.push((inst.arg.to_string(), inst.arg_range()));
+        }
+    }
+    blocks
+}
+
+fn block_item(uri: &Url, text: &str, block: &Block) -> CallHierarchyItem {
+    CallHierarchyItem {
+        name: block.label.clone(),
+        kind: SymbolKind::FIELD,
+        tags: None,
+        detail: None,
+        uri: uri.clone(),
+        range: position::range_to_lsp(text, block.range),
+        selection_range: position::range_to_lsp(text, block.name_range),
+        data: None,
+    }
+}
+
+/// The block jumps to `label` go to: like `stacky`, the last definition of a label defined
+/// more than once.
+fn target<'a>(blocks: &'a [Block], label: &str) -> Option<&'a Block> {
+    blocks.iter().rev().find(|block| block.label == label)
+}
+
+/// The label defined or jumped to at `position`, given in the client's encoding.
+pub fn prepare(uri: &Url, position: Position) -> Option<Vec<CallHierarchyItem>> {
+    let Document { text, index, .. } = document::get(uri)?;
+    let position = position::from_lsp(&text, position);
+    let (kind, name, _) = index.symbol_at(position)?;
+    if kind != document::SymbolKind::Label {
+        return None;
+    }
+    let blocks = blocks(&text);
+    let block = target(&blocks, name)?;
+    Some(vec![block_item(uri, &text, block)])
+}
+
+/// Labels whose blocks jump to the item's label, each with the jumps that do.
+pub fn incoming(item: &CallHierarchyItem) -> Option<Vec<CallHierarchyIncomingCall>> {
+    let document = document::get(&item.uri)?;
+    let text = &document.text;
+    let calls = blocks(text)
+        .iter()
+        .filter_map(|block| {
+            let from_ranges: Vec<Range> = block
+                .jumps
+                .iter()
+                .filter(|(label, _)| *label == item.name)
+                .map(|(_, range)| position::range_to_lsp(text, *range))
+                .collect();
+            (!from_ranges.is_empty()).then(|| CallHierarchyIncomingCall {
+                from: block_item(&item.uri, text, block),
+                from_ranges,
+            })
+        })
+        .collect();
+    Some(calls)
+}
+
+/// Labels the item's block jumps to, in the order first jumped to, each with the jumps that
+/// go there. Jumps to labels that aren't defined are left out.
+pub fn outgoing(item: &CallHierarchyItem) -> Option<Vec<CallHierarchyOutgoingCall>> {
+    let document = document::get(&item.uri)?;
+    let text = &document.text;
+    let blocks = blocks(text);
+    let mut calls: Vec<CallHierarchyOutgoingCall> = Vec::new();
+    let jumps = blocks
+        .iter()
+        .filter(|block| block.label == item.name)
+        .flat_map(|block| &block.jumps);
+    for (label, range) in jumps {
+        let range = position::range_to_lsp(text, *range);
+        if let Some(call) = calls.iter_mut().find(|call| call.to.name == *label) {
+            call.from_ranges.push(range);
+        } else if let Some(block) = target(&blocks, label) {
+            calls.push(CallHierarchyOutgoingCall {
+                to: block_item(&item.uri, text, block),
+                from_ranges: vec![range],
+            });
+        }
+    }
+    Some(calls)
+}
@@ -0,0 +1,192 @@
+use lsp_types::{
+    CodeAction, CodeActionKind, CodeActionOrCommand, CodeActionParams, Diagnostic, NumberOrString,
+    Position, Range, TextEdit, WorkspaceEdit,
+};
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{analysis, document, position};
+
+/// Kinds of code action the server can return, advertised to the client.
+pub fn provided_kinds() -> Vec<CodeActionKind> {
+    vec![
+        CodeActionKind::QUICKFIX,
+        CodeActionKind::SOURCE_FIX_ALL,
+        CodeActionKind::REFACTOR_REWRITE,
+    ]
+}
+
+fn is_redundant_pair(diagnostic: &Diagnostic) -> bool {
+    diagnostic.source.as_deref() == Some(analysis::LINT_SOURCE)
+        && matches!(&diagnostic.code, Some(NumberOrString::String(code)) if code == analysis::REDUNDANT_PAIR)
+}
+
+/// Name of the variable an unstored-variable diagnostic is about.
+fn unstored_variable(diagnostic: &Diagnostic) -> Option<&str> {
+    if diagnostic.source.as_deref() != Some(analysis::LINT_SOURCE)
+        || !matches!(&diagnostic.code, Some(NumberOrString::String(code)) if code == analysis::UNSTORED_VARIABLE)
+    {
+        return None;
+    }
+    diagnostic.data.as_ref()?.as_str()
+}
+
+/// Where the program starts running, for code that must run first: the start of the entry
+/// label's body, or the first instruction when no label comes first, so that a comment header
+/// stays on top. Comes with the indentation of the instructions there.
+fn entry_point(text: &str) -> (Position, String) {
+    let instructions = analysis::instructions(text);
+    let Some(first) = instructions.first() else {
+        return (Position::default(), String::new());
+    };
+    let (line, body) = match first.label() {
+        Some(_) => (
+            first.line + 1,
+            instructions.get(1).filter(|inst| inst.label().is_none()),
+        ),
+        None => (first.line, Some(first)),
+    };
+    let indent = match body {
+        Some(inst) => text.lines().nth(inst.line).unwrap_or("")[..inst.col].to_string(),
+        None => "    ".to_string(),
+    };
+    (
+        Position {
+            line: line as u32,
+            character: 0,
+        },
+        indent,
+    )
+}
+
+/// Deletes the given whole lines.
+fn delete_lines(lines: impl IntoIterator<Item = u32>) -> Vec<TextEdit> {
+    lines
+        .into_iter()
+        .map(|line| TextEdit {
+            range: Range {
+                start: Position { line, character: 0 },
+                end: Position {
+                    line: line + 1,
+                    character: 0,
+                },
+            },
+            new_text: String::new(),
+        })
+        .collect()
+}
+
+/// Returns the quick fixes for the diagnostics the client sent along with the request, and a
+/// fix-all action covering the whole document, and refactorings for the cursor's line.
+pub fn code_actions(params: &CodeActionParams) -> Vec<CodeActionOrCommand> {
+    let uri = &params.text_document.uri;
+    // kinds are hierarchical, so asking for `source` includes `source.fixAll`
+    let wanted = |kind: &CodeActionKind| {
+        params.context.only.as_ref().is_none_or(|only| {
+            only.iter().any(|requested| {
+                kind.as_str() == requested.as_str()
+                    || kind
+                        .as_str()
+                        .starts_with(&format!("{}.", requested.as_str()))
+            })
+        })
+    };
+    let mut actions = Vec::new();
+    let document = document::get(uri);
+    let quickfixes = params
+        .context
+        .diagnostics
+        .iter()
+        .filter(|_| wanted(&CodeActionKind::QUICKFIX));
+    for diagnostic in quickfixes {
+        if is_redundant_pair(diagnostic) {
+            // delete both whole lines of the pair
+            let edits = delete_lines([diagnostic.range.start.line, diagnostic.range.end.line]);
+            actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                title: "Remove redundant instructions".to_string(),
+                kind: Some(CodeActionKind::QUICKFIX),
+                diagnostics: Some(vec![diagnostic.clone()]),
+                edit: Some(WorkspaceEdit {
+                    changes: Some(HashMap::from([(uri.clone(), edits)])),
+                    ..Default::default()
+                }),
+                is_preferred: Some(true),
+                ..Default::default()
+            }));
+        }
+        if let Some(name) = unstored_variable(diagnostic) {
+            // start the program by giving the variable a value
+            let (start, indent) = document
+                .as_ref()
+                .map(|document| entry_point(&document.text))
+                .unwrap_or_default();
+            let edit = TextEdit {
+                range: Range { start, end: start },
+                new_text: format!("{0}push nil\n{0}store {1}\n", indent, name),
+            };
+            actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                title: format!("Initialize `{}` to `nil` at the program start", name),
+                kind: Some(CodeActionKind::QUICKFIX),
+                diagnostics: Some(vec![diagnostic.clone()]),
+                edit: Some(WorkspaceEdit {
+                    changes: Some(HashMap::from([(uri.clone(), vec![edit])])),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }));
+        }
+    }
+
+    let Some(document) = document else {
+        return actions;
+    };
+
+    // switch between `print` and `println` on the cursor's line
+    let line = params.range.start.line as usize;
+    let print = analysis::instructions(&document.text)
+        .into_iter()
+        .find(|inst| inst.line == line && matches!(inst.name, "print" | "println"));
+    if let Some(inst) = print.filter(|_| wanted(&CodeActionKind::REFACTOR_REWRITE)) {
+        let replacement = if inst.name == "print" {
+            "println"
+        } else {
+            "print"
+        };
+        let range = analysis::line_range(line, inst.col, inst.col + inst.name.len());
+        let edit = TextEdit {
+            range: position::range_to_lsp(&document.text, range),
+            new_text: replacement.to_string(),
+        };
+        actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+            title: format!("Convert to `{}`", replacement),
+            kind: Some(CodeActionKind::REFACTOR_REWRITE),
+            edit: Some(WorkspaceEdit {
+                changes: Some(HashMap::from([(uri.clone(), vec![edit])])),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }));
+    }
+
+    if wanted(&CodeActionKind::SOURCE_FIX_ALL) {
+        // fix exactly what the editor shows; lines are shared when pairs chain, so collect
+        // them before deleting
+        let lines: BTreeSet<u32> = crate::validate_document(uri, &document.text, document.version)
+            .diagnostics
+            .iter()
+            .filter(|diagnostic| is_redundant_pair(diagnostic))
+            .flat_map(|diagnostic| [diagnostic.range.start.line, diagnostic.range.end.line])
+            .collect();
+        if !lines.is_empty() {
+            actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                title: "Fix all auto-fixable problems".to_string(),
+                kind: Some(CodeActionKind::SOURCE_FIX_ALL),
+                edit: Some(WorkspaceEdit {
+                    changes: Some(HashMap::from([(uri.clone(), delete_lines(lines))])),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }));
+        }
+    }
+    actions
+}
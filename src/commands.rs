@@ -0,0 +1,609 @@
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::fmt;
+
+use StackCount::{Either, Exact, Variadic};
+
+/// Number of values a command pops from or pushes onto the stack.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StackCount {
+    Exact(usize),
+    /// depends on the argument or the values on the stack
+    Variadic,
+    /// one of two amounts, e.g. `assert` with or without a message
+    Either(usize, usize),
+}
+
+impl fmt::Display for StackCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Exact(n) => write!(f, "{}", n),
+            Variadic => write!(f, "n"),
+            Either(a, b) => write!(f, "{} or {}", a, b),
+        }
+    }
+}
+
+pub struct Command {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub pop: StackCount,
+    pub push: StackCount,
+}
+
+impl Command {
+    /// Renders the stack effect as a markdown table.
+    pub fn effect_table(&self) -> String {
+        format!(
+            "| Pops | Pushes |\n|:---:|:---:|\n| {} | {} |",
+            self.pop, self.push
+        )
+    }
+}
+
+const fn command(
+    name: &'static str,
+    description: &'static str,
+    pop: StackCount,
+    push: StackCount,
+) -> Command {
+    Command {
+        name,
+        description,
+        pop,
+        push,
+    }
+}
+
+pub static COMMANDS: &[Command] = &[
+    command("nop", "No operation.", Exact(0), Exact(0)),
+    command("push", "Push a value onto the stack.", Exact(0), Exact(1)),
+    command(
+        "pop",
+        "Pop the top value from the stack.",
+        Variadic,
+        Exact(0),
+    ),
+    command(
+        "add",
+        "Pop two values, push first + second.",
+        Exact(2),
+        Exact(1),
+    ),
+    command(
+        "sub",
+        "Pop two values, push first - second.",
+        Exact(2),
+        Exact(1),
+    ),
+    command(
+        "mul",
+        "Pop two values, push first * second.",
+        Exact(2),
+        Exact(1),
+    ),
+    command(
+        "div",
+        "Pop two values, push first / second.",
+        Exact(2),
+        Exact(1),
+    ),
+    command(
+        "mod",
+        "Pop two values, push first % second",
+        Exact(2),
+        Exact(1),
+    ),
+    command("neg", "Pop a value, push !value", Exact(1), Exact(1)),
+    command(
+        "dup",
+        "Duplicate the top value on the stack.",
+        Exact(1),
+        Exact(2),
+    ),
+    command(
+        "print",
+        "Pop and print the top value to output.",
+        Variadic,
+        Exact(0),
+    ),
+    command(
+        "println",
+        "Pop and print the top value to output with a newline.",
+        Variadic,
+        Exact(0),
+    ),
+    command(
+        "read",
+        "Read a value from input and push it onto the stack.",
+        Exact(0),
+        Exact(1),
+    ),
+    command("goto", "Jump to the specified label.", Exact(0), Exact(0)),
+    command(
+        "br",
+        "Pop value, if true jump to label.",
+        Exact(1),
+        Exact(0),
+    ),
+    command(
+        "load",
+        "Load a ariable and push its value.",
+        Exact(0),
+        Exact(1),
+    ),
+    command(
+        "store",
+        "Store the top of stack into a variable.",
+        Exact(1),
+        Exact(0),
+    ),
+    command(
+        "gt",
+        "Pop two values, push first > second.",
+        Exact(2),
+        Exact(1),
+    ),
+    command(
+        "lt",
+        "Pop two values, push first < second.",
+        Exact(2),
+        Exact(1),
+    ),
+    command(
+        "ge",
+        "Pop two values, push first >= second.",
+        Exact(2),
+        Exact(1),
+    ),
+    command(
+        "le",
+        "Pop two values, push first <= second.",
+        Exact(2),
+        Exact(1),
+    ),
+    command(
+        "eq",
+        "Pop two values, push first == second",
+        Exact(2),
+        Exact(1),
+    ),
+    command(
+        "ne",
+        "Pop two values, push first != second",
+        Exact(2),
+        Exact(1),
+    ),
+    command(
+        "and",
+        "Pop two values, push first & second.",
+        Exact(2),
+        Exact(1),
+    ),
+    command(
+        "or",
+        "Pop two values, push first | second.",
+        Exact(2),
+        Exact(1),
+    ),
+    command("not", "Pop a value, push !first", Exact(1), Exact(1)),
+    command(
+        "xor",
+        "Pop two values, push first ^ second.",
+        Exact(2),
+        Exact(1),
+    ),
+    command(
+        "shl",
+        "Pop two values, push first << second.",
+        Exact(2),
+        Exact(1),
+    ),
+    command(
+        "shr",
+        "Pop two values, push first >> second.",
+        Exact(2),
+        Exact(1),
+    ),
+    command("convert", "Convert value from type.", Exact(1), Exact(1)),
+    command(
+        "rotl",
+        "Pop two ints, rotate first left by second bits.",
+        Exact(2),
+        Exact(1),
+    ),
+    command(
+        "rotr",
+        "Pop two ints, rotate first right by second bits.",
+        Exact(2),
+        Exact(1),
+    ),
+    command(
+        "clz",
+        "Count leading zeros of top-of-stack integer.",
+        Exact(1),
+        Exact(1),
+    ),
+    command(
+        "ctz",
+        "Count trailing zeros of top-of-stack integer.",
+        Exact(1),
+        Exact(1),
+    ),
+    command(
+        "min",
+        "Pop two values and push the minimum.",
+        Exact(2),
+        Exact(1),
+    ),
+    command(
+        "max",
+        "Pop two values and push the maximum.",
+        Exact(2),
+        Exact(1),
+    ),
+    command(
+        "abs",
+        "Pop a value and push its absolute value.",
+        Exact(1),
+        Exact(1),
+    ),
+    command(
+        "sign",
+        "Pop a value and push -1/0/1 depending on sign.",
+        Exact(1),
+        Exact(1),
+    ),
+    command(
+        "ceil",
+        "Pop a float and push its ceiling.",
+        Exact(1),
+        Exact(1),
+    ),
+    command(
+        "floor",
+        "Pop a float and push its floor.",
+        Exact(1),
+        Exact(1),
+    ),
+    command(
+        "trunc",
+        "Pop a float and push its truncation toward zero.",
+        Exact(1),
+        Exact(1),
+    ),
+    command(
+        "sqrt",
+        "Pop a numeric value and push its square root (float).",
+        Exact(1),
+        Exact(1),
+    ),
+    command(
+        "pow",
+        "Pop two values and push first^second (as float).",
+        Exact(2),
+        Exact(1),
+    ),
+    command(
+        "sin",
+        "Pop a numeric value and push sin(value).",
+        Exact(1),
+        Exact(1),
+    ),
+    command(
+        "cos",
+        "Pop a numeric value and push cos(value).",
+        Exact(1),
+        Exact(1),
+    ),
+    command(
+        "tan",
+        "Pop a numeric value and push tan(value).",
+        Exact(1),
+        Exact(1),
+    ),
+    command(
+        "asin",
+        "Pop a numeric value and push asin(value).",
+        Exact(1),
+        Exact(1),
+    ),
+    command(
+        "acos",
+        "Pop a numeric value and push acos(value).",
+        Exact(1),
+        Exact(1),
+    ),
+    command(
+        "atan",
+        "Pop a numeric value and push atan(value).",
+        Exact(1),
+        Exact(1),
+    ),
+    command(
+        "sinh",
+        "Pop a numeric value and push sinh(value).",
+        Exact(1),
+        Exact(1),
+    ),
+    command(
+        "cosh",
+        "Pop a numeric value and push cosh(value).",
+        Exact(1),
+        Exact(1),
+    ),
+    command(
+        "tanh",
+        "Pop a numeric value and push tanh(value).",
+        Exact(1),
+        Exact(1),
+    ),
+    command(
+        "asinh",
+        "Pop a numeric value and push asinh(value).",
+        Exact(1),
+        Exact(1),
+    ),
+    command(
+        "acosh",
+        "Pop a numeric value and push acosh(value).",
+        Exact(1),
+        Exact(1),
+    ),
+    command(
+        "atanh",
+        "Pop a numeric value and push atanh(value).",
+        Exact(1),
+        Exact(1),
+    ),
+    command(
+        "exp",
+        "Pop a numeric value and push exp(value).",
+        Exact(1),
+        Exact(1),
+    ),
+    command(
+        "log",
+        "Pop a numeric value and push natural log(value).",
+        Exact(1),
+        Exact(1),
+    ),
+    command(
+        "len",
+        "Pop a string and push its length.",
+        Exact(1),
+        Exact(1),
+    ),
+    command(
+        "getarg",
+        "Pop an index and push the command-line argument at that index, or nil if out of range.",
+        Exact(1),
+        Exact(1),
+    ),
+    command(
+        "assert",
+        "Assert that the top of stack is true.",
+        Either(1, 2),
+        Exact(0),
+    ),
+    command(
+        "error",
+        "Raise a runtime error with an error message.",
+        Exact(1),
+        Exact(0),
+    ),
+    command(
+        "exit",
+        "Exit the program with provided exit code.",
+        Exact(1),
+        Exact(0),
+    ),
+];
+
+pub fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|c| c.name == name)
+}
+
+/// Levenshtein distance between two command names.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// The command `name` is most likely a misspelling of, if any is close enough.
+pub fn closest(name: &str) -> Option<&'static Command> {
+    let threshold = (name.chars().count() / 3).max(2);
+    COMMANDS
+        .iter()
+        .map(|c| (edit_distance(name, c.name), c))
+        .filter(|(distance, _)| *distance <= threshold)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, c)| c)
+}
+
+pub static SIGNATURES: Lazy<HashMap<&'static str, &'static str>> = Lazy::new(|| {
+    let mut m = HashMap::new();
+    m.insert("store", "store <var>");
+    m.insert("load", "load <var>");
+    m.insert("goto", "goto <label>");
+    m.insert("br", "br <label>");
+    m.insert("convert", "convert <type>");
+    m.insert("push", "push <value>");
+    m
+});
+
+/// Documentation for the parameter of each command in `SIGNATURES`, shown in signature help.
+pub static PARAMETERS: Lazy<HashMap<&'static str, &'static str>> = Lazy::new(|| {
+    let mut m = HashMap::new();
+    m.insert(
+        "store",
+        "Name of the variable to store the popped value in.",
+    );
+    m.insert(
+        "load",
+        "Name of a variable previously written with `store`.",
+    );
+    m.insert("goto", "Label to jump to.");
+    m.insert("br", "Label to jump to when the popped `bool` is `true`.");
+    m.insert(
+        "convert",
+        "Target type: `string`, `int`, `float`, `bool` or `nil`.",
+    );
+    m.insert(
+        "push",
+        "A literal value:\n\n\
+         - `int`: `42`, `-7`, `0x2a`, `0b101010`\n\
+         - `float`: `3.14`, `-0.5`\n\
+         - `string`: `\"hello\"`\n\
+         - `bool`: `true`, `false`\n\
+         - `nil`: `nil`",
+    );
+    m
+});
+
+/// Extra hover notes for commands whose behavior depends on program input or output.
+pub static NOTES: Lazy<HashMap<&'static str, &'static str>> = Lazy::new(|| {
+    let mut m = HashMap::new();
+    m.insert(
+        "read",
+        "Reads one line from standard input and pushes it as a `string` with surrounding \
+         whitespace trimmed. At end of input it pushes an empty string.",
+    );
+    m.insert(
+        "getarg",
+        "Takes a non-negative `int` index into the program's command-line arguments and pushes \
+         the argument as a `string`. An index past the last argument pushes `nil`; a negative \
+         index is a runtime error.",
+    );
+    m.insert(
+        "print",
+        "Writes the value to standard output without a trailing newline. Does nothing when the \
+         stack is empty. Accepts an inline value: `print \"hi\"`.",
+    );
+    m.insert(
+        "println",
+        "Writes the value to standard output followed by a newline. With an empty stack it \
+         writes just the newline. Accepts an inline value: `println \"hi\"`.",
+    );
+    m.insert(
+        "error",
+        "Stops the program with the popped `string` as the error message. The message can be \
+         given inline: `error \"bad input\"`.",
+    );
+    m.insert(
+        "exit",
+        "Stops the program with the popped `int` as the exit code. The code can be given \
+         inline: `exit 0`.",
+    );
+    m
+});
+
+/// Constants offered as inline values, by the command they follow. Any command that takes
+/// inline values can be listed; the ones here are those where a constant is a likely operand.
+pub static CONSTANTS: Lazy<HashMap<&'static str, &'static [&'static str]>> = Lazy::new(|| {
+    const ANY: &[&str] = &["true", "false", "nil"];
+    const BOOLEANS: &[&str] = &["true", "false"];
+    let mut m: HashMap<&'static str, &'static [&'static str]> = HashMap::new();
+    for name in ["push", "print", "println", "eq", "ne"] {
+        m.insert(name, ANY);
+    }
+    for name in ["and", "or", "xor", "not"] {
+        m.insert(name, BOOLEANS);
+    }
+    m
+});
+
+/// Commands the parser rejects any argument for, even inline values.
+pub static NO_ARGUMENTS: &[&str] = &["nop", "dup", "read"];
+
+/// Type names accepted by `convert`.
+pub static TYPES: &[&str] = &["string", "int", "float", "bool", "nil"];
+
+/// What `convert` does to a value of one type when asked for another.
+#[derive(Clone, Copy)]
+pub enum Conversion {
+    Identity,
+    Safe(&'static str),
+    Lossy(&'static str),
+    /// depends on the value, failing at runtime for the rest
+    Fallible(&'static str),
+    Unsupported,
+}
+
+/// Conversion matrix as `(from, to, conversion)`, mirroring the interpreter.
+static CONVERSIONS: &[(&str, &str, Conversion)] = &[
+    (
+        "string",
+        "int",
+        Conversion::Fallible("only integer literals such as `42` or `0x2a`"),
+    ),
+    ("float", "int", Conversion::Lossy("truncates toward zero")),
+    (
+        "bool",
+        "int",
+        Conversion::Safe("`true` becomes 1, `false` becomes 0"),
+    ),
+    (
+        "string",
+        "float",
+        Conversion::Fallible("only numeric strings"),
+    ),
+    (
+        "int",
+        "float",
+        Conversion::Lossy("integers beyond 2^53 lose precision"),
+    ),
+    ("int", "string", Conversion::Safe("decimal digits")),
+    (
+        "float",
+        "string",
+        Conversion::Safe("shortest round-trip form"),
+    ),
+    (
+        "int",
+        "bool",
+        Conversion::Lossy("0 becomes `false`, anything else `true`"),
+    ),
+    (
+        "string",
+        "bool",
+        Conversion::Fallible("only `\"true\"` and `\"false\"`"),
+    ),
+];
+
+pub fn conversion(from: &str, to: &str) -> Conversion {
+    if from == to {
+        return Conversion::Identity;
+    }
+    CONVERSIONS
+        .iter()
+        .find(|(f, t, _)| *f == from && *t == to)
+        .map(|(_, _, c)| *c)
+        .unwrap_or(Conversion::Unsupported)
+}
+
+/// Renders how every type converts to `to` as a markdown table.
+pub fn conversion_table(to: &str) -> String {
+    let mut table = "| From | Result |\n|---|---|".to_string();
+    for from in TYPES {
+        let result = match conversion(from, to) {
+            Conversion::Identity => "unchanged".to_string(),
+            Conversion::Safe(note) => format!("safe: {}", note),
+            Conversion::Lossy(note) => format!("**lossy**: {}", note),
+            Conversion::Fallible(note) => format!("**may fail**: {}", note),
+            Conversion::Unsupported => "**runtime error**".to_string(),
+        };
+        table.push_str(&format!("\n| `{}` | {} |", from, result));
+    }
+    table
+}
@@ -0,0 +1,68 @@
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::sync::Mutex;
+
+pub static CONFIG: Lazy<Mutex<Config>> = Lazy::new(|| Mutex::new(Config::default()));
+
+/// Server settings, read from `initializationOptions` and `workspace/didChangeConfiguration`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Config {
+    /// best-effort lints that guess at value types and may report false positives
+    pub heuristic_lints: bool,
+    /// documents larger than this are not parsed or linted
+    pub max_validate_bytes: usize,
+    /// inlay hints of any kind; when off, the two settings below are ignored
+    pub inlay_hints_enabled: bool,
+    /// stack depth hints at the end of each line
+    pub stack_depth_hints: bool,
+    /// type hints after the variable of each `store`
+    pub variable_type_hints: bool,
+    /// hint when execution can reach the end of the program without an `exit`
+    pub require_exit: bool,
+    /// commands the target runtime lacks; hidden from completion and warned about when used
+    pub disabled_commands: Vec<String>,
+    /// document and range formatting line up inline comments within each label section
+    pub align_comments: bool,
+    /// show a message summarizing a document's problems when it is opened
+    pub notify_on_open: bool,
+    /// completion lists longer than this are cut short and marked incomplete
+    pub max_completion_items: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            heuristic_lints: false,
+            max_validate_bytes: 1024 * 1024,
+            inlay_hints_enabled: true,
+            stack_depth_hints: true,
+            variable_type_hints: true,
+            require_exit: false,
+            disabled_commands: Vec::new(),
+            align_comments: false,
+            notify_on_open: false,
+            max_completion_items: 100,
+        }
+    }
+}
+
+impl Config {
+    pub fn is_disabled(&self, command: &str) -> bool {
+        self.disabled_commands.iter().any(|name| name == command)
+    }
+}
+
+/// Replaces the current settings. Accepts either the settings object itself or one nested
+/// under a `stacky` section, as sent by most clients for `didChangeConfiguration`.
+pub fn update(value: serde_json::Value) {
+    let value = match value {
+        serde_json::Value::Object(mut map) if map.contains_key("stacky") => map.remove("stacky"),
+        serde_json::Value::Null => return,
+        value => Some(value),
+    };
+    match serde_json::from_value::<Config>(value.unwrap_or_default()) {
+        Ok(config) => *CONFIG.lock().unwrap() = config,
+        Err(err) => eprintln!("Ignoring invalid stacky configuration: {}", err),
+    }
+}
@@ -0,0 +1,195 @@
+use lsp_types::{Position, Range, Url};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::analysis;
+use crate::stack::{self, StackDepth};
+
+/// Open documents by URI.
+pub static DOCUMENTS: Lazy<Mutex<HashMap<Url, Document>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Clone, Default)]
+pub struct Document {
+    pub text: String,
+    /// version the client gave this text, or `None` for a file read from disk that the
+    /// client hasn't opened
+    pub version: Option<i32>,
+    pub index: DocumentIndex,
+}
+
+impl Document {
+    pub fn new(text: String, version: Option<i32>) -> Self {
+        let index = DocumentIndex::new(&text);
+        Document {
+            text,
+            version,
+            index,
+        }
+    }
+}
+
+/// Returns a copy of the tracked document, if any.
+pub fn get(uri: &Url) -> Option<Document> {
+    DOCUMENTS.lock().unwrap().get(uri).cloned()
+}
+
+/// Reads a document the client hasn't opened from disk. Only `file` URIs are read.
+pub fn read(uri: &Url) -> Option<Document> {
+    if uri.scheme() != "file" {
+        return None;
+    }
+    let text = std::fs::read_to_string(uri.to_file_path().ok()?).ok()?;
+    Some(Document::new(text, None))
+}
+
+/// Like `get`, but a `.stacky` file that isn't tracked is read from disk, for requests that
+/// arrive before the client opens it. What is read is not tracked, so it is never validated
+/// or published until the client opens it.
+pub fn get_or_read(uri: &Url) -> Option<Document> {
+    get(uri).or_else(|| {
+        Some(uri)
+            .filter(|uri| uri.path().ends_with(".stacky"))
+            .and_then(read)
+    })
+}
+
+/// Returns the version of the tracked document, if any.
+pub fn version(uri: &Url) -> Option<Option<i32>> {
+    DOCUMENTS
+        .lock()
+        .unwrap()
+        .get(uri)
+        .map(|document| document.version)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SymbolKind {
+    Label,
+    Variable,
+}
+
+/// Where each label and variable is defined and used, built once per document change.
+/// Ranges use byte columns.
+#[derive(Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentIndex {
+    /// label definitions, ranging over the name without the `:`
+    pub labels: Vec<(String, Range)>,
+    /// `store` operands
+    pub stores: Vec<(String, Range)>,
+    /// `load` operands
+    pub loads: Vec<(String, Range)>,
+    /// `goto` and `br` operands
+    pub jumps: Vec<(String, Range)>,
+    /// stack depth before each reachable instruction
+    pub stack_depths: Vec<StackDepth>,
+    /// how many times each command is used
+    pub command_counts: HashMap<String, usize>,
+}
+
+fn contains(range: &Range, position: Position) -> bool {
+    range.start <= position && position <= range.end
+}
+
+fn find_site(sites: &[(String, Range)], position: Position) -> Option<(&str, Range)> {
+    sites
+        .iter()
+        .find(|(_, range)| contains(range, position))
+        .map(|(name, range)| (name.as_str(), *range))
+}
+
+impl DocumentIndex {
+    pub fn new(text: &str) -> Self {
+        let instructions = analysis::instructions(text);
+        let mut index = DocumentIndex {
+            stack_depths: stack::stack_depths(&instructions),
+            ..Default::default()
+        };
+        for inst in instructions {
+            if let Some(label) = inst.label() {
+                index.labels.push((label.to_string(), inst.name_range()));
+                continue;
+            }
+            *index
+                .command_counts
+                .entry(inst.name.to_string())
+                .or_default() += 1;
+            if inst.arg.is_empty() {
+                continue;
+            }
+            let site = (inst.arg.to_string(), inst.arg_range());
+            match inst.name {
+                "store" => index.stores.push(site),
+                "load" => index.loads.push(site),
+                "goto" | "br" => index.jumps.push(site),
+                _ => {}
+            }
+        }
+        index
+    }
+
+    /// Returns the label or variable under `position` along with its name and range.
+    pub fn symbol_at(&self, position: Position) -> Option<(SymbolKind, &str, Range)> {
+        let find = |sites| find_site(sites, position);
+        if let Some((name, range)) = find(&self.labels).or_else(|| find(&self.jumps)) {
+            return Some((SymbolKind::Label, name, range));
+        }
+        let (name, range) = find(&self.stores).or_else(|| find(&self.loads))?;
+        Some((SymbolKind::Variable, name, range))
+    }
+
+    /// Returns the first `goto` or `br` operand jumping to the label defined under
+    /// `position`, if the position is on a label definition.
+    pub fn first_jump_to(&self, position: Position) -> Option<Range> {
+        let (label, _) = find_site(&self.labels, position)?;
+        self.jumps
+            .iter()
+            .find(|(name, _)| name == label)
+            .map(|(_, range)| *range)
+    }
+
+    /// Returns the definition of the label jumped to by the `goto` or `br` operand under
+    /// `position`. Like `stacky`, a label defined twice resolves to its last definition.
+    pub fn label_definition(&self, position: Position) -> Option<Range> {
+        let (label, _) = find_site(&self.jumps, position)?;
+        self.labels
+            .iter()
+            .rfind(|(name, _)| name == label)
+            .map(|(_, range)| *range)
+    }
+
+    /// Returns every definition and use of the given symbol, in document order.
+    pub fn occurrences(&self, kind: SymbolKind, name: &str) -> Vec<Range> {
+        self.references(kind, name, true)
+    }
+
+    /// Returns every use of the given symbol in document order, along with its definitions
+    /// if `include_definitions` is set: label definitions for a label, `store`s for a variable.
+    pub fn references(
+        &self,
+        kind: SymbolKind,
+        name: &str,
+        include_definitions: bool,
+    ) -> Vec<Range> {
+        let (definitions, uses) = match kind {
+            SymbolKind::Label => (&self.labels, &self.jumps),
+            SymbolKind::Variable => (&self.stores, &self.loads),
+        };
+        let definitions = if include_definitions {
+            definitions.as_slice()
+        } else {
+            &[]
+        };
+        let mut result: Vec<Range> = definitions
+            .iter()
+            .chain(uses)
+            .filter(|(n, _)| n == name)
+            .map(|(_, range)| *range)
+            .collect();
+        result.sort_by_key(|range| range.start);
+        result
+    }
+}
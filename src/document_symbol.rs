@@ -0,0 +1,83 @@
+use lsp_types::{DocumentSymbol, Range, SymbolKind};
+
+use crate::analysis::{self, Instruction};
+use crate::position;
+
+/// Symbols sent in each `$/progress` notification when the client streams the outline.
+pub const CHUNK_SIZE: usize = 100;
+
+#[allow(deprecated)]
+fn symbol(name: &str, kind: SymbolKind, range: Range, selection_range: Range) -> DocumentSymbol {
+    DocumentSymbol {
+        name: name.to_string(),
+        detail: None,
+        kind,
+        tags: None,
+        deprecated: None,
+        range,
+        selection_range,
+        children: None,
+    }
+}
+
+fn variable(inst: &Instruction) -> DocumentSymbol {
+    symbol(
+        inst.arg,
+        SymbolKind::VARIABLE,
+        inst.range(),
+        inst.arg_range(),
+    )
+}
+
+/// The document's outline, in byte columns: each label spanning its block up to the last
+/// instruction before the next label, with the variables the block first stores as children.
+/// Variables stored above the first label are top-level symbols.
+fn outline(text: &str) -> Vec<DocumentSymbol> {
+    let mut symbols: Vec<DocumentSymbol> = Vec::new();
+    let mut in_label = false;
+    for inst in analysis::instructions(text) {
+        if let Some(label) = inst.label() {
+            symbols.push(symbol(
+                label,
+                SymbolKind::FIELD,
+                inst.range(),
+                inst.name_range(),
+            ));
+            in_label = true;
+            continue;
+        }
+        if in_label {
+            let block = symbols.last_mut().unwrap();
+            block.range.end = inst.range().end;
+            if inst.name == "store" && !inst.arg.is_empty() {
+                let children = block.children.get_or_insert_with(Vec::new);
+                if !children.iter().any(|child| child.name == inst.arg) {
+                    children.push(variable(&inst));
+                }
+            }
+        } else if inst.name == "store"
+            && !inst.arg.is_empty()
+            && !symbols.iter().any(|symbol| symbol.name == inst.arg)
+        {
+            symbols.push(variable(&inst));
+        }
+    }
+    symbols
+}
+
+fn to_lsp(text: &str, symbol: &mut DocumentSymbol) {
+    symbol.range = position::range_to_lsp(text, symbol.range);
+    symbol.selection_range = position::range_to_lsp(text, symbol.selection_range);
+    for child in symbol.children.iter_mut().flatten() {
+        to_lsp(text, child);
+    }
+}
+
+/// The document's labels and variables, with ranges in the client's encoding.
+pub fn document_symbols(text: &str) -> Vec<DocumentSymbol> {
+    let mut symbols = outline(text);
+    for symbol in &mut symbols {
+        to_lsp(text, symbol);
+    }
+    symbols
+}
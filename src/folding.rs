@@ -0,0 +1,58 @@
+use lsp_types::{FoldingRange, FoldingRangeKind};
+
+use crate::analysis;
+
+/// Lines a run of full-line comments needs before it folds, so short notes stay put.
+const MIN_COMMENT_RUN: usize = 3;
+
+/// Folds each label's block of instructions, up to the last one before the next label, and
+/// each run of consecutive full-line comments, such as a license banner.
+pub fn folding_ranges(text: &str) -> Vec<FoldingRange> {
+    let mut ranges = label_blocks(text);
+    let mut run_start = None;
+    let lines: Vec<&str> = text.lines().collect();
+    // the extra blank line closes a run that reaches the end of the file
+    for (line, raw) in lines.iter().chain([&""]).enumerate() {
+        let is_comment = raw.trim_start().starts_with(';');
+        match (is_comment, run_start) {
+            (true, None) => run_start = Some(line),
+            (false, Some(start)) => {
+                if line - start >= MIN_COMMENT_RUN {
+                    ranges.push(FoldingRange {
+                        start_line: start as u32,
+                        end_line: (line - 1) as u32,
+                        kind: Some(FoldingRangeKind::Comment),
+                        ..Default::default()
+                    });
+                }
+                run_start = None;
+            }
+            _ => {}
+        }
+    }
+    ranges
+}
+
+/// From each label to the last instruction before the next label, so that comments and blank
+/// lines leading up to the next label stay visible. A label with nothing under it doesn't fold.
+fn label_blocks(text: &str) -> Vec<FoldingRange> {
+    let instructions = analysis::instructions(text);
+    let mut ranges = Vec::new();
+    for (i, inst) in instructions.iter().enumerate() {
+        if inst.label().is_none() {
+            continue;
+        }
+        let end = instructions[i + 1..]
+            .iter()
+            .take_while(|next| next.label().is_none())
+            .last();
+        if let Some(end) = end {
+            ranges.push(FoldingRange {
+                start_line: inst.line as u32,
+                end_line: end.line as u32,
+                ..Default::default()
+            });
+        }
+    }
+    ranges
+}
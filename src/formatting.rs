@@ -0,0 +1,99 @@
+use lsp_types::{DocumentOnTypeFormattingParams, FormattingOptions, TextEdit};
+use std::ops::RangeInclusive;
+
+use crate::{analysis, config, position};
+
+/// One level of indentation as the client asked for it.
+fn indent_unit(options: &FormattingOptions) -> String {
+    if options.insert_spaces {
+        " ".repeat(options.tab_size as usize)
+    } else {
+        "\t".to_string()
+    }
+}
+
+fn leading_whitespace(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+
+/// Indents a freshly started line: one level below a label, flush left below a flush-left
+/// line, and level with any other instruction. Blank lines in between are skipped.
+pub fn on_type_formatting(text: &str, params: &DocumentOnTypeFormattingParams) -> Vec<TextEdit> {
+    let line = params.text_document_position.position.line as usize;
+    let lines: Vec<&str> = text.lines().collect();
+    let Some(previous) = lines[..line.min(lines.len())]
+        .iter()
+        .rposition(|l| !l.trim().is_empty())
+    else {
+        return Vec::new();
+    };
+    let is_label = analysis::instructions(lines[previous])
+        .first()
+        .is_some_and(|inst| inst.label().is_some());
+    let indent = if is_label {
+        format!(
+            "{}{}",
+            leading_whitespace(lines[previous]),
+            indent_unit(&params.options)
+        )
+    } else {
+        leading_whitespace(lines[previous]).to_string()
+    };
+    let current = leading_whitespace(lines.get(line).copied().unwrap_or(""));
+    if current == indent {
+        return Vec::new();
+    }
+    vec![TextEdit {
+        range: analysis::line_range(line, 0, current.len()),
+        new_text: indent,
+    }]
+}
+
+/// Edits for the lines in `lines`. Formatting only lines up inline comments, and only when
+/// `alignComments` is on; the indentation is left to on-type formatting.
+pub fn format(text: &str, lines: RangeInclusive<usize>) -> Vec<TextEdit> {
+    if !config::CONFIG.lock().unwrap().align_comments {
+        return Vec::new();
+    }
+    align_comments(text, lines)
+}
+
+/// Pads the inline comments of each label section to one column past its widest
+/// instruction that has one. Sections are measured whole, so formatting part of one still
+/// lines up with the rest.
+fn align_comments(text: &str, lines: RangeInclusive<usize>) -> Vec<TextEdit> {
+    let text_lines: Vec<&str> = text.lines().collect();
+    // (line, end of the code, start of the comment, width of the code)
+    let mut section: Vec<(usize, usize, usize, usize)> = Vec::new();
+    let mut edits = Vec::new();
+    let mut flush = |section: &mut Vec<(usize, usize, usize, usize)>| {
+        let column = section.iter().map(|&(.., width)| width).max().unwrap_or(0) + 1;
+        for (line, code_end, comment, width) in section.drain(..) {
+            if !lines.contains(&line) || comment - code_end == column - width {
+                continue;
+            }
+            let range = analysis::line_range(line, code_end, comment);
+            edits.push(TextEdit {
+                range: position::range_to_lsp(text, range),
+                new_text: " ".repeat(column - width),
+            });
+        }
+    };
+    let instructions = analysis::instructions(text);
+    for (line, raw) in text_lines.iter().enumerate() {
+        let starts_section = instructions
+            .iter()
+            .any(|inst| inst.line == line && inst.label().is_some());
+        if starts_section {
+            flush(&mut section);
+        }
+        let comment = analysis::strip_comment(raw).len();
+        let code = raw[..comment].trim_end();
+        if comment == raw.len() || code.trim_start().is_empty() {
+            continue;
+        }
+        section.push((line, code.len(), comment, code.chars().count()));
+    }
+    flush(&mut section);
+    edits
+}
@@ -0,0 +1,129 @@
+use lsp_types::{
+    InlayHint, InlayHintKind, InlayHintLabel, InlayHintParams, InlayHintTooltip, MarkupContent,
+    MarkupKind, Url,
+};
+use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+
+use crate::analysis::{self, Instruction};
+use crate::stack::{self, ValueType};
+use crate::{config, document, position};
+
+/// Carried from a hint to its resolve request, which computes the tooltip.
+#[derive(Serialize, Deserialize)]
+struct HintData {
+    uri: Url,
+    line: usize,
+}
+
+/// Stack depth after each reachable instruction in the requested range, at the end of its
+/// line, and the type of the value each `store` saves, after its variable. Lines where the
+/// depth or type can't be tracked get no hint.
+pub fn inlay_hints(params: &InlayHintParams) -> Option<Vec<InlayHint>> {
+    let uri = &params.text_document.uri;
+    let document = document::get(uri)?;
+    let config = config::CONFIG.lock().unwrap().clone();
+    if !config.inlay_hints_enabled {
+        return Some(Vec::new());
+    }
+    let lines = params.range.start.line as usize..=params.range.end.line as usize;
+    let instructions = analysis::instructions(&document.text);
+    let mut hints = Vec::new();
+    if config.variable_type_hints {
+        hints.extend(type_hints(&document.text, &instructions, &lines));
+    }
+    if config.stack_depth_hints {
+        hints.extend(
+            document
+                .index
+                .stack_depths
+                .iter()
+                .filter(|depth| lines.contains(&depth.line))
+                .filter_map(|depth| {
+                    let after = depth.after?;
+                    let inst = instructions.iter().find(|inst| inst.line == depth.line)?;
+                    let end = position::range_to_lsp(&document.text, inst.range()).end;
+                    Some(InlayHint {
+                        position: end,
+                        label: InlayHintLabel::String(format!("stack: {}", after)),
+                        kind: None,
+                        text_edits: None,
+                        tooltip: None,
+                        padding_left: Some(true),
+                        padding_right: None,
+                        data: serde_json::to_value(HintData {
+                            uri: uri.clone(),
+                            line: depth.line,
+                        })
+                        .ok(),
+                    })
+                }),
+        );
+    }
+    Some(hints)
+}
+
+/// `: int` and the like after the variable of each `store` on `lines`, from the type on top of
+/// the stack before it runs. Stores of a value whose type is unknown are skipped.
+fn type_hints(
+    text: &str,
+    instructions: &[Instruction],
+    lines: &RangeInclusive<usize>,
+) -> Vec<InlayHint> {
+    let stacks = stack::stacks_before(instructions);
+    instructions
+        .iter()
+        .zip(stacks)
+        .filter(|(inst, _)| inst.name == "store" && !inst.arg.is_empty())
+        .filter(|(inst, _)| lines.contains(&inst.line))
+        .filter_map(|(inst, stack)| {
+            let ty = stack?.types().next()?;
+            if ty == ValueType::Unknown {
+                return None;
+            }
+            Some(InlayHint {
+                position: position::range_to_lsp(text, inst.arg_range()).end,
+                label: InlayHintLabel::String(format!(": {}", ty)),
+                kind: Some(InlayHintKind::TYPE),
+                text_edits: None,
+                tooltip: None,
+                padding_left: None,
+                padding_right: None,
+                data: None,
+            })
+        })
+        .collect()
+}
+
+/// Fills in the tooltip listing the type of every value on the stack after the hint's line.
+pub fn resolve(mut hint: InlayHint) -> InlayHint {
+    let Some(data) = hint
+        .data
+        .clone()
+        .and_then(|data| serde_json::from_value::<HintData>(data).ok())
+    else {
+        return hint;
+    };
+    let Some(document) = document::get(&data.uri) else {
+        return hint;
+    };
+    let instructions = analysis::instructions(&document.text);
+    let Some(stack) = stack::stack_after(&instructions, data.line) else {
+        return hint;
+    };
+    let value = if stack.depth == 0 {
+        "The stack is empty.".to_string()
+    } else {
+        let slots: Vec<String> = stack
+            .types()
+            .enumerate()
+            .map(|(i, ty)| format!("{}. `{}`", i + 1, ty))
+            .collect();
+        format!("Stack after this line, top first:\n\n{}", slots.join("\n"))
+    };
+    hint.tooltip = Some(InlayHintTooltip::MarkupContent(MarkupContent {
+        kind: MarkupKind::Markdown,
+        value,
+    }));
+    hint
+}
@@ -1,324 +1,227 @@
-use lsp_server::{Connection, Message, Request, Response};
+mod analysis;
+mod call_hierarchy;
+mod code_action;
+mod commands;
+mod config;
+mod document;
+mod document_symbol;
+mod folding;
+mod formatting;
+mod inlay_hint;
+mod position;
+mod selection_range;
+mod semantic_tokens;
+mod stack;
+mod workspace_symbol;
+
+use commands::{COMMANDS, NOTES, PARAMETERS, SIGNATURES, StackCount};
+use document::{DOCUMENTS, Document, SymbolKind};
+use lsp_server::{Connection, ErrorCode, Message, Request, RequestId, Response};
 use lsp_types::{
-    CompletionItem, CompletionItemKind, CompletionParams, CompletionResponse, Diagnostic,
-    DiagnosticSeverity, DidChangeTextDocumentParams, DidOpenTextDocumentParams, InitializeResult,
-    MarkupContent, MarkupKind, Range, ServerCapabilities, TextDocumentSyncCapability,
-    TextDocumentSyncKind, Url,
+    CallHierarchyIncomingCallsParams, CallHierarchyOutgoingCallsParams, CallHierarchyPrepareParams,
+    CallHierarchyServerCapability, ClientCapabilities, CodeActionOptions, CodeActionParams,
+    CodeActionProviderCapability, CompletionItem, CompletionItemKind, CompletionList,
+    CompletionParams, CompletionResponse, CompletionTextEdit, CompletionTriggerKind, Diagnostic,
+    DiagnosticSeverity, DidChangeConfigurationParams, DidChangeTextDocumentParams,
+    DidChangeWatchedFilesParams, DidChangeWatchedFilesRegistrationOptions,
+    DidCloseTextDocumentParams, DidOpenTextDocumentParams, DocumentFilter,
+    DocumentFormattingParams, DocumentHighlight, DocumentHighlightKind, DocumentHighlightParams,
+    DocumentOnTypeFormattingOptions, DocumentOnTypeFormattingParams,
+    DocumentOnTypeFormattingRegistrationOptions, DocumentRangeFormattingParams,
+    DocumentSymbolParams, DocumentSymbolResponse, Documentation, ExecuteCommandOptions,
+    ExecuteCommandParams, FileChangeType, FileSystemWatcher, FoldingRangeParams,
+    FoldingRangeProviderCapability, GlobPattern, GotoDefinitionParams, GotoDefinitionResponse,
+    ImplementationProviderCapability, InitializeParams, InitializeResult, InlayHint,
+    InlayHintOptions, InlayHintParams, InlayHintServerCapabilities, InsertReplaceEdit,
+    InsertTextFormat, LinkedEditingRangeParams, LinkedEditingRangeServerCapabilities,
+    LinkedEditingRanges, Location, MarkupContent, MarkupKind, MessageType, OneOf,
+    ParameterInformation, ParameterLabel, PrepareRenameResponse, ProgressToken, Range,
+    ReferenceParams, Registration, RegistrationParams, RenameOptions, RenameParams,
+    SelectionRangeParams, SelectionRangeProviderCapability, SemanticTokens,
+    SemanticTokensDeltaParams, SemanticTokensFullOptions, SemanticTokensOptions,
+    SemanticTokensParams, SemanticTokensRangeParams, SemanticTokensRangeResult,
+    SemanticTokensResult, SemanticTokensServerCapabilities, ServerCapabilities, ShowMessageParams,
+    SignatureHelp, SignatureHelpContext, SignatureHelpParams, SignatureInformation,
+    TextDocumentPositionParams, TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Url,
+    WorkspaceEdit, WorkspaceSymbol, WorkspaceSymbolOptions, WorkspaceSymbolParams,
+    WorkspaceSymbolResponse,
 };
 use once_cell::sync::Lazy;
-use std::collections::HashMap;
-use std::sync::Mutex;
-
-static LATEST_TEXT: Lazy<Mutex<String>> = Lazy::new(|| Mutex::new(String::new()));
-static COMMANDS: Lazy<Vec<(&'static str, &'static str, &'static str)>> = Lazy::new(|| {
-    // (name, description, stack_effect)
-    vec![
-        ("nop", "No operation.", "Pop 0 | Push 0"),
-        ("push", "Push a value onto the stack.", "Pop 0 | Push 1"),
-        (
-            "pop",
-            "Pop the top value from the stack.",
-            "Pop 1(n) | Push 0",
-        ),
-        (
-            "add",
-            "Pop two values, push first + second.",
-            "Pop 2 | Push 1",
-        ),
-        (
-            "sub",
-            "Pop two values, push first - second.",
-            "Pop 2 | Push 1",
-        ),
-        (
-            "mul",
-            "Pop two values, push first * second.",
-            "Pop 2 | Push 1",
-        ),
-        (
-            "div",
-            "Pop two values, push first / second.",
-            "Pop 2 | Push 1",
-        ),
-        (
-            "mod",
-            "Pop two values, push first % second",
-            "Pop 2 | Push 1",
-        ),
-        (
-            "neg",
-            "Pop a value, push !value",
-            "Pop 1 | Push 1",
-        ),
-        (
-            "dup",
-            "Duplicate the top value on the stack.",
-            "Pop 1 | Push 2",
-        ),
-        (
-            "print",
-            "Pop and print the top value to output.",
-            "Pop n | Push 0",
-        ),
-        (
-            "println",
-            "Pop and print the top value to output with a newline.",
-            "Pop n | Push 0",
-        ),
-        (
-            "read",
-            "Read a value from input and push it onto the stack.",
-            "Pop 0 | Push 1",
-        ),
-        ("goto", "Jump to the specified label.", "Pop 0 | Push 0"),
-        (
-            "br",
-            "Pop value, if true jump to label.",
-            "Pop 1 | Push 0",
-        ),
-        (
-            "load",
-            "Load a ariable and push its value.",
-            "Pop 0 | Push 1",
-        ),
-        (
-            "store",
-            "Store the top of stack into a variable.",
-            "Pop 1 | Push 0",
-        ),
-        (
-            "gt",
-            "Pop two values, push first > second.",
-            "Pop 2 | Push 1",
-        ),
-        (
-            "lt",
-            "Pop two values, push first < second.",
-            "Pop 2 | Push 1",
-        ),
-        (
-            "ge",
-            "Pop two values, push first >= second.",
-            "Pop 2 | Push 1",
-        ),
-        (
-            "le",
-            "Pop two values, push first <= second.",
-            "Pop 2 | Push 1",
-        ),
-        (
-            "eq",
-            "Pop two values, push first == second",
-            "Pop 2 | Push 1",
-        ),
-        (
-            "ne",
-            "Pop two values, push first != second",
-            "Pop 2 | Push 1",
-        ),
-        ("and", "Pop two values, push first & second.", "Pop 2 | Push 1"),
-        ("or", "Pop two values, push first | second.", "Pop 2 | Push 1"),
-        ("not", "Pop a value, push !first", "Pop 1 | Push 1"),
-        ("xor", "Pop two values, push first ^ second.", "Pop 2 | Push 1"),
-        (
-            "shl",
-            "Pop two values, push first << second.",
-            "Pop 2 | Push 1",
-        ),
-        (
-            "shr",
-            "Pop two values, push first >> second.",
-            "Pop 2 | Push 1",
-        ),
-        ("convert", "Convert value from type.", "Pop 1 | Push 1"),
-        (
-            "rotl",
-            "Pop two ints, rotate first left by second bits.",
-            "Pop 2 | Push 1",
-        ),
-        (
-            "rotr",
-            "Pop two ints, rotate first right by second bits.",
-            "Pop 2 | Push 1",
-        ),
-        (
-            "clz",
-            "Count leading zeros of top-of-stack integer.",
-            "Pop 1 | Push 1",
-        ),
-        (
-            "ctz",
-            "Count trailing zeros of top-of-stack integer.",
-            "Pop 1 | Push 1",
-        ),
-        (
-            "min",
-            "Pop two values and push the minimum.",
-            "Pop 2 | Push 1",
-        ),
-        (
-            "max",
-            "Pop two values and push the maximum.",
-            "Pop 2 | Push 1",
-        ),
-        (
-            "abs",
-            "Pop a value and push its absolute value.",
-            "Pop 1 | Push 1",
-        ),
-        (
-            "sign",
-            "Pop a value and push -1/0/1 depending on sign.",
-            "Pop 1 | Push 1",
-        ),
-        (
-            "ceil",
-            "Pop a float and push its ceiling.",
-            "Pop 1 | Push 1",
-        ),
-        (
-            "floor",
-            "Pop a float and push its floor.",
-            "Pop 1 | Push 1",
-        ),
-        (
-            "trunc",
-            "Pop a float and push its truncation toward zero.",
-            "Pop 1 | Push 1",
-        ),
-        (
-            "sqrt",
-            "Pop a numeric value and push its square root (float).",
-            "Pop 1 | Push 1",
-        ),
-        (
-            "pow",
-            "Pop two values and push first^second (as float).",
-            "Pop 2 | Push 1",
-        ),
-        (
-            "sin",
-            "Pop a numeric value and push sin(value).",
-            "Pop 1 | Push 1",
-        ),
-        (
-            "cos",
-            "Pop a numeric value and push cos(value).",
-            "Pop 1 | Push 1",
-        ),
-        (
-            "tan",
-            "Pop a numeric value and push tan(value).",
-            "Pop 1 | Push 1",
-        ),
-        (
-            "asin",
-            "Pop a numeric value and push asin(value).",
-            "Pop 1 | Push 1",
-        ),
-        (
-            "acos",
-            "Pop a numeric value and push acos(value).",
-            "Pop 1 | Push 1",
-        ),
-        (
-            "atan",
-            "Pop a numeric value and push atan(value).",
-            "Pop 1 | Push 1",
-        ),
-        (
-            "sinh",
-            "Pop a numeric value and push sinh(value).",
-            "Pop 1 | Push 1",
-        ),
-        (
-            "cosh",
-            "Pop a numeric value and push cosh(value).",
-            "Pop 1 | Push 1",
-        ),
-        (
-            "tanh",
-            "Pop a numeric value and push tanh(value).",
-            "Pop 1 | Push 1",
-        ),
-        (
-            "asinh",
-            "Pop a numeric value and push asinh(value).",
-            "Pop 1 | Push 1",
-        ),
-        (
-            "acosh",
-            "Pop a numeric value and push acosh(value).",
-            "Pop 1 | Push 1",
-        ),
-        (
-            "atanh",
-            "Pop a numeric value and push atanh(value).",
-            "Pop 1 | Push 1",
-        ),
-        (
-            "exp",
-            "Pop a numeric value and push exp(value).",
-            "Pop 1 | Push 1",
-        ),
-        (
-            "log",
-            "Pop a numeric value and push natural log(value).",
-            "Pop 1 | Push 1",
-        ),
-        (
-            "len",
-            "Pop a string and push its length.",
-            "Pop 1 | Push 1",
-        ),
-        (
-            "getarg",
-            "Pop an index and push the command-line argument at that index, or nil if out of range.",
-            "Pop 1 | Push 1",
-        ),
-        (
-            "assert",
-            "Assert that the top of stack is true.",
-            "Pop 1(2) | Push 0",
-        ),
-        (
-            "error",
-            "Raise a runtime error with an error message.",
-            "Pop 1 | Push 0",
-        ),
-        (
-            "exit",
-            "Exit the program with provided exit code.",
-            "Pop 1 | Push 0",
-        ),
-    ]
-});
-
-static SIGNATURES: Lazy<HashMap<&'static str, &'static str>> = Lazy::new(|| {
-    let mut m = HashMap::new();
-    m.insert("store", "store <var>");
-    m.insert("load", "load <var>");
-    m.insert("goto", "goto <label>");
-    m.insert("br", "br <label>");
-    m.insert("convert", "convert <type>");
-    m
-});
+use serde::Serialize;
+use stack::ValueType;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, mpsc};
+
+static CLIENT_CAPABILITIES: Lazy<Mutex<ClientCapabilities>> =
+    Lazy::new(|| Mutex::new(ClientCapabilities::default()));
+
+/// Whether contributor-only commands like `stacky.debug.dump` are enabled.
+static DEBUG: Lazy<Mutex<bool>> = Lazy::new(|| Mutex::new(false));
+
+/// What the validator last published, reported by `stacky/status`.
+#[derive(Default)]
+struct ValidationStatus {
+    /// diagnostics per document, with ranges as the client sees them
+    diagnostics: HashMap<Url, Vec<Diagnostic>>,
+    /// whether the last document validated came out without errors
+    last_succeeded: Option<bool>,
+    /// open documents whose first validation has been summarized for `notifyOnOpen`
+    summarized: HashSet<Url>,
+}
+
+static VALIDATION_STATUS: Lazy<Mutex<ValidationStatus>> =
+    Lazy::new(|| Mutex::new(ValidationStatus::default()));
+
+/// Response to the `stacky/status` request.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Status {
+    documents: usize,
+    diagnostics: usize,
+    version: &'static str,
+    last_validation_succeeded: Option<bool>,
+}
+
+/// Typing this character re-indents the new line.
+const ON_TYPE_FORMATTING_TRIGGER: &str = "\n";
+
+/// What a label name can look like while it is edited in linked mode: the characters
+/// `rename` would accept.
+const LABEL_WORD_PATTERN: &str = r#"[^\s;:"]+"#;
+
+/// Returns the analysis state of the document given as the first argument.
+const DEBUG_DUMP_COMMAND: &str = "stacky.debug.dump";
+
+/// Given a text document position, return the location of the next or previous diagnostic in
+/// that document, wrapping around at either end.
+const NEXT_DIAGNOSTIC_COMMAND: &str = "stacky.nextDiagnostic";
+const PREV_DIAGNOSTIC_COMMAND: &str = "stacky.prevDiagnostic";
+
+/// Validates the document given as the first argument, or every tracked document without
+/// one, and returns the diagnostics for each.
+const LINT_COMMAND: &str = "stacky.lint";
+
+/// Given a text document position, return a `WorkspaceEdit` inserting `SKELETON` there.
+const INSERT_SKELETON_COMMAND: &str = "stacky.insertSkeleton";
+
+/// A minimal runnable program to start a new file from.
+const SKELETON: &str = "main:\n    push \"Hello, world!\"\n    println\n    exit 0\n";
+
+const USAGE: &str = "\
+Usage: stacky-lsp [OPTIONS]
+
+Options:
+  --stdio        Communicate over stdin/stdout (default)
+  --debug        Enable the `stacky.debug.dump` command
+  -h, --help     Print this help and exit
+  -V, --version  Print the version and exit
+";
 
 fn main() -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--stdio" => {}
+            "--debug" => *DEBUG.lock().unwrap() = true,
+            "-V" | "--version" => {
+                println!("stacky-lsp {}", env!("CARGO_PKG_VERSION"));
+                return Ok(());
+            }
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                return Ok(());
+            }
+            _ => {
+                eprintln!("error: unknown flag `{}`\n\n{}", arg, USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
+
     eprintln!("Starting stacky LSP server");
 
     let (connection, io_threads) = Connection::stdio();
+    run(&connection)?;
+    // the writer thread finishes once every sender is gone
+    drop(connection);
+    io_threads.join()?;
+
+    eprintln!("Shutting down stacky LSP server");
+    Ok(())
+}
 
+/// Serves one client until it asks to shut down or disconnects. Independent of the transport,
+/// so the server can also be driven over an in-memory `Connection::memory()` pair.
+fn run(connection: &Connection) -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
+    let debug = *DEBUG.lock().unwrap();
     let server_capabilities = ServerCapabilities {
-        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(
+            TextDocumentSyncKind::INCREMENTAL,
+        )),
         completion_provider: Some(lsp_types::CompletionOptions {
             resolve_provider: Some(false),
-            trigger_characters: Some(vec![" ".to_string()]),
+            // not `:`, as nothing can follow a label on its line; the new line after it
+            // triggers instead
+            trigger_characters: Some(vec![" ".to_string(), "\n".to_string()]),
             ..Default::default()
         }),
         hover_provider: Some(lsp_types::HoverProviderCapability::Simple(true)),
+        code_action_provider: Some(CodeActionProviderCapability::Options(CodeActionOptions {
+            code_action_kinds: Some(code_action::provided_kinds()),
+            ..Default::default()
+        })),
+        rename_provider: Some(OneOf::Right(RenameOptions {
+            prepare_provider: Some(true),
+            work_done_progress_options: Default::default(),
+        })),
         signature_help_provider: Some(lsp_types::SignatureHelpOptions {
             trigger_characters: Some(vec![" ".to_string()]),
+            retrigger_characters: Some(vec![" ".to_string()]),
+            ..Default::default()
+        }),
+        document_formatting_provider: Some(OneOf::Left(true)),
+        document_range_formatting_provider: Some(OneOf::Left(true)),
+        document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
+            first_trigger_character: ON_TYPE_FORMATTING_TRIGGER.to_string(),
+            more_trigger_character: None,
+        }),
+        folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+        workspace_symbol_provider: Some(OneOf::Right(WorkspaceSymbolOptions {
+            resolve_provider: Some(true),
+            work_done_progress_options: Default::default(),
+        })),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        references_provider: Some(OneOf::Left(true)),
+        semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
+            SemanticTokensOptions {
+                legend: semantic_tokens::legend(),
+                full: Some(SemanticTokensFullOptions::Delta { delta: Some(true) }),
+                range: Some(true),
+                ..Default::default()
+            },
+        )),
+        document_highlight_provider: Some(OneOf::Left(true)),
+        selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
+        call_hierarchy_provider: Some(CallHierarchyServerCapability::Simple(true)),
+        implementation_provider: Some(ImplementationProviderCapability::Simple(true)),
+        linked_editing_range_provider: Some(LinkedEditingRangeServerCapabilities::Simple(true)),
+        inlay_hint_provider: Some(OneOf::Right(InlayHintServerCapabilities::Options(
+            InlayHintOptions {
+                resolve_provider: Some(true),
+                ..Default::default()
+            },
+        ))),
+        execute_command_provider: Some(ExecuteCommandOptions {
+            commands: [
+                NEXT_DIAGNOSTIC_COMMAND,
+                PREV_DIAGNOSTIC_COMMAND,
+                LINT_COMMAND,
+                INSERT_SKELETON_COMMAND,
+            ]
+            .into_iter()
+            .chain(debug.then_some(DEBUG_DUMP_COMMAND))
+            .map(str::to_string)
+            .collect(),
             ..Default::default()
         }),
         ..Default::default()
@@ -326,12 +229,34 @@ fn main() -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
 
     eprintln!("Started stacky LSP server");
 
+    let sender = connection.sender.clone();
+    let validator = spawn_validator(move |notification| {
+        sender.send(Message::Notification(notification)).is_ok()
+    });
+
     for msg in &connection.receiver {
         match msg {
             Message::Request(req) => {
                 if req.method == "initialize" {
+                    // tolerate minimal clients: anything missing falls back to the defaults
+                    let params: InitializeParams = serde_json::from_value(req.params)
+                        .unwrap_or_else(|err| {
+                            eprintln!("Invalid initialize params, using defaults: {}", err);
+                            InitializeParams::default()
+                        });
+                    let mut capabilities = server_capabilities.clone();
+                    capabilities.position_encoding =
+                        Some(position::negotiate(&params.capabilities));
+                    // registered once the client is initialized instead
+                    if dynamic_on_type_formatting(&params.capabilities) {
+                        capabilities.document_on_type_formatting_provider = None;
+                    }
+                    *CLIENT_CAPABILITIES.lock().unwrap() = params.capabilities;
+                    if let Some(options) = params.initialization_options {
+                        config::update(options);
+                    }
                     let result = InitializeResult {
-                        capabilities: server_capabilities.clone(),
+                        capabilities,
                         server_info: None,
                     };
                     let resp = Response {
@@ -342,31 +267,71 @@ fn main() -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
                     connection.sender.send(Message::Response(resp))?;
                     continue;
                 }
-                if req.method == "shutdown" {
-                    let resp = Response {
-                        id: req.id,
-                        result: Some(serde_json::Value::Null),
-                        error: None,
-                    };
-                    connection.sender.send(Message::Response(resp))?;
+                // waits for the exit notification too, so the reader thread can hand it over
+                if connection.handle_shutdown(&req)? {
                     return Ok(());
                 }
-                handle_request(&connection, req)?;
+                handle_request(connection, req)?;
             }
             Message::Response(_) => {}
             Message::Notification(notification) => {
                 if notification.method == "initialized" {
                     eprintln!("Initialized stacky LSP server");
+                    register_capabilities(connection)?;
                 } else {
-                    handle_notification(&connection, notification)?;
+                    handle_notification(&validator, notification)?;
                 }
             }
         }
     }
 
-    io_threads.join()?;
+    Ok(())
+}
 
-    eprintln!("Shutting down stacky LSP server");
+/// Location of the first diagnostic last published for the document that starts after the
+/// position, or before it when going backwards, wrapping around at either end.
+fn adjacent_diagnostic(at: &TextDocumentPositionParams, forward: bool) -> Option<Location> {
+    let status = VALIDATION_STATUS.lock().unwrap();
+    let diagnostics = status.diagnostics.get(&at.text_document.uri)?;
+    let position = at.position;
+    let diagnostic = if forward {
+        diagnostics
+            .iter()
+            .find(|d| d.range.start > position)
+            .or_else(|| diagnostics.first())
+    } else {
+        diagnostics
+            .iter()
+            .rev()
+            .find(|d| d.range.start < position)
+            .or_else(|| diagnostics.last())
+    }?;
+    Some(Location {
+        uri: at.text_document.uri.clone(),
+        range: diagnostic.range,
+    })
+}
+
+/// A batch of results for a request the client gave a partial result token.
+#[derive(Serialize)]
+struct PartialResult<T> {
+    token: ProgressToken,
+    value: T,
+}
+
+fn send_partial_result<T: Serialize>(
+    connection: &Connection,
+    token: &ProgressToken,
+    value: T,
+) -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
+    let params = PartialResult {
+        token: token.clone(),
+        value,
+    };
+    let notification = lsp_server::Notification::new("$/progress".to_string(), params);
+    connection
+        .sender
+        .send(Message::Notification(notification))?;
     Ok(())
 }
 
@@ -377,8 +342,454 @@ fn handle_request(
     match req.method.as_str() {
         "textDocument/completion" => {
             let params: CompletionParams = serde_json::from_value(req.params)?;
-            let completions = get_completions(&params);
-            let result = CompletionResponse::Array(completions);
+            // a document we never saw opened has nothing to complete against
+            let result = document::get(&params.text_document_position.text_document.uri)
+                .map(|doc| CompletionResponse::List(get_completions(&doc, &params)));
+            let resp = Response {
+                id: req.id,
+                result: Some(serde_json::to_value(result)?),
+                error: None,
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
+        "textDocument/hover" => {
+            // Handle hover: params contain textDocument and position
+            let params: lsp_types::HoverParams = serde_json::from_value(req.params)?;
+            let text_document_position = params.text_document_position_params;
+            // a hover can race the didOpen for a file the editor is just showing
+            let hover_result = document::get_or_read(&text_document_position.text_document.uri)
+                // clamped, such a position would land on the last word of the line
+                .filter(|doc| !position::past_line_end(&doc.text, text_document_position.position))
+                .and_then(|doc| {
+                    let position = position::from_lsp(&doc.text, text_document_position.position);
+                    get_hover(&doc, position)
+                });
+            let resp = Response {
+                id: req.id,
+                result: Some(serde_json::to_value(hover_result)?),
+                error: None,
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
+        "textDocument/signatureHelp" => {
+            let params: SignatureHelpParams = serde_json::from_value(req.params)?;
+            let text_document_position = params.text_document_position_params;
+            let result = document::get(&text_document_position.text_document.uri).and_then(|doc| {
+                let position = position::from_lsp(&doc.text, text_document_position.position);
+                get_signature_help(&doc.text, position, params.context.as_ref())
+            });
+            let resp = Response {
+                id: req.id,
+                result: Some(serde_json::to_value(result)?),
+                error: None,
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
+        "workspace/executeCommand" => {
+            let params: ExecuteCommandParams = serde_json::from_value(req.params)?;
+            let argument = params.arguments.into_iter().next();
+            let result = match params.command.as_str() {
+                NEXT_DIAGNOSTIC_COMMAND | PREV_DIAGNOSTIC_COMMAND => {
+                    let forward = params.command == NEXT_DIAGNOSTIC_COMMAND;
+                    let location = argument
+                        .and_then(|arg| {
+                            serde_json::from_value::<TextDocumentPositionParams>(arg).ok()
+                        })
+                        .and_then(|at| adjacent_diagnostic(&at, forward));
+                    serde_json::to_value(location)?
+                }
+                INSERT_SKELETON_COMMAND => {
+                    let edit = argument
+                        .and_then(|arg| {
+                            serde_json::from_value::<TextDocumentPositionParams>(arg).ok()
+                        })
+                        .map(|at| {
+                            let insert = TextEdit {
+                                range: Range {
+                                    start: at.position,
+                                    end: at.position,
+                                },
+                                new_text: SKELETON.to_string(),
+                            };
+                            WorkspaceEdit {
+                                changes: Some(HashMap::from([(
+                                    at.text_document.uri,
+                                    vec![insert],
+                                )])),
+                                ..Default::default()
+                            }
+                        });
+                    serde_json::to_value(edit)?
+                }
+                LINT_COMMAND => {
+                    let uris: Vec<Url> = match argument {
+                        Some(uri) => match serde_json::from_value(uri) {
+                            Ok(uri) => vec![uri],
+                            Err(err) => {
+                                let resp = Response::new_err(
+                                    req.id,
+                                    ErrorCode::InvalidParams as i32,
+                                    format!("`{}` takes a document URI: {}", LINT_COMMAND, err),
+                                );
+                                connection.sender.send(Message::Response(resp))?;
+                                return Ok(());
+                            }
+                        },
+                        None => DOCUMENTS.lock().unwrap().keys().cloned().collect(),
+                    };
+                    // a file the client never opened is read from disk but not tracked
+                    let reports: Vec<lsp_types::PublishDiagnosticsParams> = uris
+                        .into_iter()
+                        .filter_map(|uri| {
+                            let doc = document::get(&uri).or_else(|| document::read(&uri))?;
+                            Some(validate_document(&uri, &doc.text, doc.version))
+                        })
+                        .collect();
+                    serde_json::to_value(reports)?
+                }
+                DEBUG_DUMP_COMMAND if *DEBUG.lock().unwrap() => {
+                    let index = argument
+                        .and_then(|uri| serde_json::from_value::<Url>(uri).ok())
+                        .and_then(|uri| document::get(&uri))
+                        .map(|doc| doc.index);
+                    serde_json::to_value(index)?
+                }
+                command => {
+                    let resp = Response::new_err(
+                        req.id,
+                        ErrorCode::InvalidParams as i32,
+                        format!("unknown command `{}`", command),
+                    );
+                    connection.sender.send(Message::Response(resp))?;
+                    return Ok(());
+                }
+            };
+            let resp = Response {
+                id: req.id,
+                result: Some(result),
+                error: None,
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
+        "textDocument/formatting" => {
+            let params: DocumentFormattingParams = serde_json::from_value(req.params)?;
+            let edits = document::get(&params.text_document.uri)
+                .map(|doc| formatting::format(&doc.text, 0..=usize::MAX));
+            let resp = Response {
+                id: req.id,
+                result: Some(serde_json::to_value(edits)?),
+                error: None,
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
+        "textDocument/rangeFormatting" => {
+            let params: DocumentRangeFormattingParams = serde_json::from_value(req.params)?;
+            let lines = params.range.start.line as usize..=params.range.end.line as usize;
+            let edits = document::get(&params.text_document.uri)
+                .map(|doc| formatting::format(&doc.text, lines));
+            let resp = Response {
+                id: req.id,
+                result: Some(serde_json::to_value(edits)?),
+                error: None,
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
+        "textDocument/onTypeFormatting" => {
+            let params: DocumentOnTypeFormattingParams = serde_json::from_value(req.params)?;
+            let edits = document::get(&params.text_document_position.text_document.uri)
+                .map(|doc| formatting::on_type_formatting(&doc.text, &params));
+            let resp = Response {
+                id: req.id,
+                result: Some(serde_json::to_value(edits)?),
+                error: None,
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
+        "stacky/status" => {
+            let documents = DOCUMENTS.lock().unwrap().len();
+            let status = VALIDATION_STATUS.lock().unwrap();
+            let result = Status {
+                documents,
+                diagnostics: status.diagnostics.values().map(Vec::len).sum(),
+                version: env!("CARGO_PKG_VERSION"),
+                last_validation_succeeded: status.last_succeeded,
+            };
+            drop(status);
+            let resp = Response {
+                id: req.id,
+                result: Some(serde_json::to_value(result)?),
+                error: None,
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
+        "textDocument/inlayHint" => {
+            let params: InlayHintParams = serde_json::from_value(req.params)?;
+            let hints = inlay_hint::inlay_hints(&params);
+            let resp = Response {
+                id: req.id,
+                result: Some(serde_json::to_value(hints)?),
+                error: None,
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
+        "inlayHint/resolve" => {
+            let hint: InlayHint = serde_json::from_value(req.params)?;
+            let resp = Response {
+                id: req.id,
+                result: Some(serde_json::to_value(inlay_hint::resolve(hint))?),
+                error: None,
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
+        "workspace/symbol" => {
+            let params: WorkspaceSymbolParams = serde_json::from_value(req.params)?;
+            let lazy = workspace_symbol::lazy_ranges(&CLIENT_CAPABILITIES.lock().unwrap());
+            let batches = workspace_symbol::symbols(&params.query, lazy);
+            // with a partial result token each document's symbols go out as soon as they are
+            // ready, and the response itself carries none
+            let symbols = match params.partial_result_params.partial_result_token {
+                Some(token) => {
+                    for batch in batches {
+                        send_partial_result(connection, &token, batch)?;
+                    }
+                    Vec::new()
+                }
+                None => batches.concat(),
+            };
+            let resp = Response {
+                id: req.id,
+                result: Some(serde_json::to_value(WorkspaceSymbolResponse::Nested(
+                    symbols,
+                ))?),
+                error: None,
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
+        "workspaceSymbol/resolve" => {
+            let symbol: WorkspaceSymbol = serde_json::from_value(req.params)?;
+            let resp = Response {
+                id: req.id,
+                result: Some(serde_json::to_value(workspace_symbol::resolve(symbol))?),
+                error: None,
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
+        "textDocument/documentSymbol" => {
+            let params: DocumentSymbolParams = serde_json::from_value(req.params)?;
+            let symbols = document::get(&params.text_document.uri)
+                .map(|doc| document_symbol::document_symbols(&doc.text));
+            // a long outline goes out a chunk at a time, and the response itself carries none
+            let symbols = match (symbols, params.partial_result_params.partial_result_token) {
+                (Some(symbols), Some(token)) => {
+                    for chunk in symbols.chunks(document_symbol::CHUNK_SIZE) {
+                        send_partial_result(connection, &token, chunk)?;
+                    }
+                    Some(Vec::new())
+                }
+                (symbols, _) => symbols,
+            };
+            let resp = Response {
+                id: req.id,
+                result: Some(serde_json::to_value(
+                    symbols.map(DocumentSymbolResponse::Nested),
+                )?),
+                error: None,
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
+        "textDocument/foldingRange" => {
+            let params: FoldingRangeParams = serde_json::from_value(req.params)?;
+            let ranges = document::get(&params.text_document.uri)
+                .map(|doc| folding::folding_ranges(&doc.text));
+            let resp = Response {
+                id: req.id,
+                result: Some(serde_json::to_value(ranges)?),
+                error: None,
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
+        "textDocument/semanticTokens/full" => {
+            let params: SemanticTokensParams = serde_json::from_value(req.params)?;
+            let uri = &params.text_document.uri;
+            let result = document::get(uri)
+                .map(|doc| SemanticTokensResult::Tokens(semantic_tokens::full(uri, &doc.text)));
+            let resp = Response {
+                id: req.id,
+                result: Some(serde_json::to_value(result)?),
+                error: None,
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
+        "textDocument/semanticTokens/full/delta" => {
+            let params: SemanticTokensDeltaParams = serde_json::from_value(req.params)?;
+            let uri = &params.text_document.uri;
+            let result = document::get(uri)
+                .map(|doc| semantic_tokens::delta(uri, &doc.text, &params.previous_result_id));
+            let resp = Response {
+                id: req.id,
+                result: Some(serde_json::to_value(result)?),
+                error: None,
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
+        "textDocument/semanticTokens/range" => {
+            let params: SemanticTokensRangeParams = serde_json::from_value(req.params)?;
+            let lines = params.range.start.line as usize..=params.range.end.line as usize;
+            let result = document::get(&params.text_document.uri).map(|doc| {
+                SemanticTokensRangeResult::Tokens(SemanticTokens {
+                    result_id: None,
+                    data: semantic_tokens::tokens(&doc.text, lines),
+                })
+            });
+            let resp = Response {
+                id: req.id,
+                result: Some(serde_json::to_value(result)?),
+                error: None,
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
+        "textDocument/selectionRange" => {
+            let params: SelectionRangeParams = serde_json::from_value(req.params)?;
+            let ranges = document::get(&params.text_document.uri)
+                .map(|doc| selection_range::selection_ranges(&doc.text, &params.positions));
+            let resp = Response {
+                id: req.id,
+                result: Some(serde_json::to_value(ranges)?),
+                error: None,
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
+        "textDocument/codeAction" => {
+            let params: CodeActionParams = serde_json::from_value(req.params)?;
+            let actions = code_action::code_actions(&params);
+            let resp = Response {
+                id: req.id,
+                result: Some(serde_json::to_value(actions)?),
+                error: None,
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
+        "textDocument/prepareRename" => {
+            let params: TextDocumentPositionParams = serde_json::from_value(req.params)?;
+            // the symbol's own name is always a valid rename target, so this only fails
+            // when there is nothing to rename under the cursor
+            let result = document::get(&params.text_document.uri).and_then(|doc| {
+                let Document { text, index, .. } = doc;
+                let position = position::from_lsp(&text, params.position);
+                index
+                    .symbol_at(position)
+                    .and_then(|(_, name, _)| analysis::rename_edits(&index, position, name).ok())
+                    .and_then(|edits| {
+                        edits
+                            .into_iter()
+                            .map(|(range, _)| range)
+                            .find(|range| range.start <= position && position <= range.end)
+                    })
+                    .map(|range| PrepareRenameResponse::Range(position::range_to_lsp(&text, range)))
+            });
+            let resp = Response {
+                id: req.id,
+                result: Some(serde_json::to_value(result)?),
+                error: None,
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
+        "textDocument/definition" => {
+            let params: GotoDefinitionParams = serde_json::from_value(req.params)?;
+            let at = params.text_document_position_params;
+            let result = document::get(&at.text_document.uri).and_then(|doc| {
+                let position = position::from_lsp(&doc.text, at.position);
+                let range = doc.index.label_definition(position)?;
+                Some(GotoDefinitionResponse::Scalar(Location {
+                    uri: at.text_document.uri.clone(),
+                    range: position::range_to_lsp(&doc.text, range),
+                }))
+            });
+            let resp = Response {
+                id: req.id,
+                result: Some(serde_json::to_value(result)?),
+                error: None,
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
+        "textDocument/references" => {
+            let params: ReferenceParams = serde_json::from_value(req.params)?;
+            let at = params.text_document_position;
+            let result = document::get(&at.text_document.uri).and_then(|doc| {
+                let Document { text, index, .. } = doc;
+                let position = position::from_lsp(&text, at.position);
+                let (kind, name, _) = index.symbol_at(position)?;
+                let locations: Vec<Location> = index
+                    .references(kind, name, params.context.include_declaration)
+                    .into_iter()
+                    .map(|range| Location {
+                        uri: at.text_document.uri.clone(),
+                        range: position::range_to_lsp(&text, range),
+                    })
+                    .collect();
+                Some(locations)
+            });
+            let resp = Response {
+                id: req.id,
+                result: Some(serde_json::to_value(result)?),
+                error: None,
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
+        "textDocument/documentHighlight" => {
+            let params: DocumentHighlightParams = serde_json::from_value(req.params)?;
+            let at = params.text_document_position_params;
+            let result = document::get(&at.text_document.uri).and_then(|doc| {
+                let Document { text, index, .. } = doc;
+                let position = position::from_lsp(&text, at.position);
+                let (kind, name, _) = index.symbol_at(position)?;
+                // label definitions and stores write the name; jumps and loads read it
+                let uses = index.references(kind, name, false);
+                let highlights: Vec<DocumentHighlight> = index
+                    .occurrences(kind, name)
+                    .into_iter()
+                    .map(|range| DocumentHighlight {
+                        range: position::range_to_lsp(&text, range),
+                        kind: Some(if uses.contains(&range) {
+                            DocumentHighlightKind::READ
+                        } else {
+                            DocumentHighlightKind::WRITE
+                        }),
+                    })
+                    .collect();
+                Some(highlights)
+            });
+            let resp = Response {
+                id: req.id,
+                result: Some(serde_json::to_value(result)?),
+                error: None,
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
+        "textDocument/prepareCallHierarchy" => {
+            let params: CallHierarchyPrepareParams = serde_json::from_value(req.params)?;
+            let at = params.text_document_position_params;
+            let result = call_hierarchy::prepare(&at.text_document.uri, at.position);
+            let resp = Response {
+                id: req.id,
+                result: Some(serde_json::to_value(result)?),
+                error: None,
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
+        "callHierarchy/incomingCalls" => {
+            let params: CallHierarchyIncomingCallsParams = serde_json::from_value(req.params)?;
+            let result = call_hierarchy::incoming(&params.item);
+            let resp = Response {
+                id: req.id,
+                result: Some(serde_json::to_value(result)?),
+                error: None,
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
+        "callHierarchy/outgoingCalls" => {
+            let params: CallHierarchyOutgoingCallsParams = serde_json::from_value(req.params)?;
+            let result = call_hierarchy::outgoing(&params.item);
             let resp = Response {
                 id: req.id,
                 result: Some(serde_json::to_value(result)?),
@@ -386,105 +797,246 @@ fn handle_request(
             };
             connection.sender.send(Message::Response(resp))?;
         }
-        "textDocument/hover" => {
-            // Handle hover: params contain textDocument and position
-            let params: lsp_types::HoverParams = serde_json::from_value(req.params)?;
-            // try to find the token under cursor in the latest text
-            let text = LATEST_TEXT.lock().unwrap().clone();
-            let pos = params.text_document_position_params.position;
-            let line_idx = pos.line as usize;
-            let mut hover_result: Option<lsp_types::Hover> = None;
-            let lines: Vec<&str> = text.lines().collect();
-            if line_idx < lines.len() {
-                let l = lines[line_idx];
-                // determine cursor column and extract the token under cursor (better than split_whitespace)
-                let col = params.text_document_position_params.position.character as usize;
-                let col = col.min(l.len());
-
-                // find start of word (search backward for whitespace)
-                let start = l[..col]
-                    .rfind(|c: char| c.is_whitespace())
-                    .map(|p| p + 1)
-                    .unwrap_or(0);
-                // find end of word (search forward for whitespace)
-                let end = l[col..]
-                    .find(|c: char| c.is_whitespace())
-                    .map(|p| col + p)
-                    .unwrap_or(l.len());
-
-                let mut token = l[start..end]
-                    .trim_matches(|c: char| !c.is_alphanumeric() && c != '_')
-                    .to_string();
-                // if token empty, try a fallback: split_whitespace and pick a non-empty
-                if token.is_empty() {
-                    token = l
-                        .split_whitespace()
-                        .find(|s| !s.is_empty())
-                        .unwrap_or("")
-                        .to_string();
-                }
-
-                if !token.is_empty() {
-                    for (name, description, effect) in COMMANDS.iter() {
-                        if token == *name {
-                            let display = if let Some(sig) = SIGNATURES.get(name) {
-                                sig.to_string()
-                            } else {
-                                name.to_string()
-                            };
-                            let md = format!(
-                                "```stacky\n{}\n```\n\n{}\n\n---\n\n{}",
-                                display, description, effect
-                            );
-                            hover_result = Some(lsp_types::Hover {
-                                contents: lsp_types::HoverContents::Markup(MarkupContent {
-                                    kind: MarkupKind::Markdown,
-                                    value: md,
-                                }),
-                                range: None,
-                            });
-                            break;
-                        }
-                    }
+        "textDocument/implementation" => {
+            // the inverse of a definition: from a label to the first jump that uses it
+            let params: lsp_types::request::GotoImplementationParams =
+                serde_json::from_value(req.params)?;
+            let at = params.text_document_position_params;
+            let result = document::get(&at.text_document.uri).and_then(|doc| {
+                let position = position::from_lsp(&doc.text, at.position);
+                let range = doc.index.first_jump_to(position)?;
+                Some(GotoDefinitionResponse::Scalar(Location {
+                    uri: at.text_document.uri.clone(),
+                    range: position::range_to_lsp(&doc.text, range),
+                }))
+            });
+            let resp = Response {
+                id: req.id,
+                result: Some(serde_json::to_value(result)?),
+                error: None,
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
+        "textDocument/linkedEditingRange" => {
+            let params: LinkedEditingRangeParams = serde_json::from_value(req.params)?;
+            let at = params.text_document_position_params;
+            // only labels: a variable's store and loads are edited together by rename alone
+            let result = document::get(&at.text_document.uri).and_then(|doc| {
+                let Document { text, index, .. } = doc;
+                let position = position::from_lsp(&text, at.position);
+                let (kind, name, _) = index.symbol_at(position)?;
+                if kind != SymbolKind::Label {
+                    return None;
                 }
-            }
+                let ranges = index
+                    .occurrences(kind, name)
+                    .into_iter()
+                    .map(|range| position::range_to_lsp(&text, range))
+                    .collect();
+                Some(LinkedEditingRanges {
+                    ranges,
+                    word_pattern: Some(LABEL_WORD_PATTERN.to_string()),
+                })
+            });
             let resp = Response {
                 id: req.id,
-                result: Some(serde_json::to_value(hover_result)?),
+                result: Some(serde_json::to_value(result)?),
                 error: None,
             };
             connection.sender.send(Message::Response(resp))?;
         }
+        "textDocument/rename" => {
+            let params: RenameParams = serde_json::from_value(req.params)?;
+            let uri = params.text_document_position.text_document.uri;
+            let Some(Document { text, index, .. }) = document::get(&uri) else {
+                connection.sender.send(Message::Response(Response::new_ok(
+                    req.id,
+                    serde_json::Value::Null,
+                )))?;
+                return Ok(());
+            };
+            let position = position::from_lsp(&text, params.text_document_position.position);
+            let resp = match analysis::rename_edits(&index, position, &params.new_name) {
+                Ok(edits) => {
+                    let edits = edits
+                        .into_iter()
+                        .map(|(range, new_text)| TextEdit {
+                            range: position::range_to_lsp(&text, range),
+                            new_text,
+                        })
+                        .collect();
+                    let edit = WorkspaceEdit {
+                        changes: Some(HashMap::from([(uri, edits)])),
+                        ..Default::default()
+                    };
+                    Response::new_ok(req.id, edit)
+                }
+                Err(err) => {
+                    Response::new_err(req.id, ErrorCode::RequestFailed as i32, err.to_string())
+                }
+            };
+            connection.sender.send(Message::Response(resp))?;
+        }
         _ => {}
     }
     Ok(())
 }
 
-fn handle_notification(
+/// Whether the client wants on-type formatting registered dynamically rather than announced
+/// in the initialize result.
+fn dynamic_on_type_formatting(capabilities: &ClientCapabilities) -> bool {
+    capabilities
+        .text_document
+        .as_ref()
+        .and_then(|t| t.on_type_formatting.as_ref())
+        .and_then(|f| f.dynamic_registration)
+        .unwrap_or(false)
+}
+
+/// Registers the features the client asked to have registered dynamically: watching
+/// `.stacky` files on disk and on-type formatting.
+fn register_capabilities(
     connection: &Connection,
+) -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
+    let client = CLIENT_CAPABILITIES.lock().unwrap().clone();
+    let mut registrations = Vec::new();
+    let watch_files = client
+        .workspace
+        .as_ref()
+        .and_then(|w| w.did_change_watched_files.as_ref())
+        .and_then(|w| w.dynamic_registration)
+        .unwrap_or(false);
+    if watch_files {
+        let options = DidChangeWatchedFilesRegistrationOptions {
+            watchers: vec![FileSystemWatcher {
+                glob_pattern: GlobPattern::String("**/*.stacky".to_string()),
+                kind: None,
+            }],
+        };
+        registrations.push(Registration {
+            id: "stacky-watched-files".to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+            register_options: Some(serde_json::to_value(options)?),
+        });
+    }
+    if dynamic_on_type_formatting(&client) {
+        let options = DocumentOnTypeFormattingRegistrationOptions {
+            document_selector: Some(vec![DocumentFilter {
+                language: Some("stacky".to_string()),
+                scheme: None,
+                pattern: None,
+            }]),
+            first_trigger_character: ON_TYPE_FORMATTING_TRIGGER.to_string(),
+            more_trigger_character: None,
+        };
+        registrations.push(Registration {
+            id: "stacky-on-type-formatting".to_string(),
+            method: "textDocument/onTypeFormatting".to_string(),
+            register_options: Some(serde_json::to_value(options)?),
+        });
+    }
+    if registrations.is_empty() {
+        return Ok(());
+    }
+    let request = Request::new(
+        RequestId::from("stacky/registerCapabilities".to_string()),
+        "client/registerCapability".to_string(),
+        RegistrationParams { registrations },
+    );
+    connection.sender.send(Message::Request(request))?;
+    Ok(())
+}
+
+fn handle_notification(
+    validator: &mpsc::Sender<Url>,
     notification: lsp_server::Notification,
 ) -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
     match notification.method.as_str() {
         "textDocument/didOpen" => {
             let params: DidOpenTextDocumentParams = serde_json::from_value(notification.params)?;
-            {
-                let mut latest = LATEST_TEXT.lock().unwrap();
-                *latest = params.text_document.text.clone();
+            // a client may route other files here by mistake; they'd only get nonsense diagnostics
+            if params.text_document.language_id != "stacky" {
+                return Ok(());
             }
-            validate_document(
-                connection,
-                &params.text_document.uri,
-                &params.text_document.text,
-            )?;
+            let document = Document::new(
+                params.text_document.text,
+                Some(params.text_document.version),
+            );
+            DOCUMENTS
+                .lock()
+                .unwrap()
+                .insert(params.text_document.uri.clone(), document);
+            validator.send(params.text_document.uri)?;
         }
         "textDocument/didChange" => {
             let params: DidChangeTextDocumentParams = serde_json::from_value(notification.params)?;
-            if let Some(change) = params.content_changes.first() {
-                {
-                    let mut latest = LATEST_TEXT.lock().unwrap();
-                    *latest = change.text.clone();
+            let uri = params.text_document.uri;
+            // only documents accepted on open are tracked
+            let Some(mut text) = document::get(&uri).map(|document| document.text) else {
+                return Ok(());
+            };
+            // each change applies to the text as left by the one before it
+            for change in params.content_changes {
+                match change.range {
+                    Some(range) => {
+                        let start = position::to_text_offset(&text, range.start);
+                        let end = position::to_text_offset(&text, range.end).max(start);
+                        text.replace_range(start..end, &change.text);
+                    }
+                    None => text = change.text,
+                }
+            }
+            // index outside the lock so lookups on other documents aren't held up
+            let document = Document::new(text, Some(params.text_document.version));
+            match DOCUMENTS.lock().unwrap().get_mut(&uri) {
+                Some(tracked) => *tracked = document,
+                None => return Ok(()),
+            }
+            validator.send(uri)?;
+        }
+        "textDocument/didClose" => {
+            let params: DidCloseTextDocumentParams = serde_json::from_value(notification.params)?;
+            let uri = params.text_document.uri;
+            // the validator clears what was published once it finds the document gone
+            if DOCUMENTS.lock().unwrap().remove(&uri).is_some() {
+                semantic_tokens::forget(&uri);
+                validator.send(uri)?;
+            }
+        }
+        "workspace/didChangeWatchedFiles" => {
+            let params: DidChangeWatchedFilesParams = serde_json::from_value(notification.params)?;
+            for change in params.changes {
+                if !change.uri.path().ends_with(".stacky") {
+                    continue;
+                }
+                // the client owns the text of open documents and reports its own changes
+                if document::version(&change.uri).is_some_and(|version| version.is_some()) {
+                    continue;
                 }
-                validate_document(connection, &params.text_document.uri, &change.text)?;
+                let document = Some(&change.uri)
+                    .filter(|_| change.typ != FileChangeType::DELETED)
+                    .and_then(document::read);
+                match document {
+                    Some(document) => {
+                        DOCUMENTS
+                            .lock()
+                            .unwrap()
+                            .insert(change.uri.clone(), document);
+                    }
+                    None => {
+                        DOCUMENTS.lock().unwrap().remove(&change.uri);
+                    }
+                }
+                validator.send(change.uri)?;
+            }
+        }
+        "workspace/didChangeConfiguration" => {
+            let params: DidChangeConfigurationParams = serde_json::from_value(notification.params)?;
+            config::update(params.settings);
+            // lints may have been switched on or off, so diagnostics already shown are stale
+            let uris: Vec<Url> = DOCUMENTS.lock().unwrap().keys().cloned().collect();
+            for uri in uris {
+                validator.send(uri)?;
             }
         }
         _ => {}
@@ -492,51 +1044,140 @@ fn handle_notification(
     Ok(())
 }
 
+/// Validates documents on a background thread so a slow document doesn't hold up hover and
+/// completion. Each queued URI is validated once, in the order queued, against whatever text
+/// is current by then; diagnostics for text that changed while being validated are dropped
+/// rather than published out of date. The thread stops once `send` returns false.
+fn spawn_validator(
+    send: impl Fn(lsp_server::Notification) -> bool + Send + 'static,
+) -> mpsc::Sender<Url> {
+    let (sender, receiver) = mpsc::channel::<Url>();
+    std::thread::spawn(move || {
+        let publish = |params: lsp_types::PublishDiagnosticsParams| {
+            send(lsp_server::Notification::new(
+                "textDocument/publishDiagnostics".to_string(),
+                params,
+            ))
+        };
+        while let Ok(first) = receiver.recv() {
+            let mut queued: Vec<Url> = Vec::new();
+            for uri in std::iter::once(first).chain(receiver.try_iter()) {
+                if !queued.contains(&uri) {
+                    queued.push(uri);
+                }
+            }
+            for uri in queued {
+                // no longer tracked, so whatever was published for it is stale
+                let Some(document) = document::get(&uri) else {
+                    let cleared = lsp_types::PublishDiagnosticsParams {
+                        uri: uri.clone(),
+                        diagnostics: Vec::new(),
+                        version: None,
+                    };
+                    let mut status = VALIDATION_STATUS.lock().unwrap();
+                    status.diagnostics.remove(&uri);
+                    status.summarized.remove(&uri);
+                    drop(status);
+                    if !publish(cleared) {
+                        return;
+                    }
+                    continue;
+                };
+                let params = validate_document(&uri, &document.text, document.version);
+                if document::version(&uri) != Some(document.version) {
+                    continue;
+                }
+                let mut status = VALIDATION_STATUS.lock().unwrap();
+                status.last_succeeded = Some(
+                    !params
+                        .diagnostics
+                        .iter()
+                        .any(|d| d.severity == Some(DiagnosticSeverity::ERROR)),
+                );
+                status
+                    .diagnostics
+                    .insert(uri.clone(), params.diagnostics.clone());
+                // only the first validation after the client opens the document
+                let summarize = document.version.is_some()
+                    && status.summarized.insert(uri.clone())
+                    && config::CONFIG.lock().unwrap().notify_on_open;
+                drop(status);
+                let summary = summarize
+                    .then(|| diagnostic_summary(&uri, &params.diagnostics))
+                    .flatten();
+                if !publish(params) {
+                    return;
+                }
+                if let Some(message) = summary {
+                    let params = ShowMessageParams {
+                        typ: MessageType::INFO,
+                        message,
+                    };
+                    let notification =
+                        lsp_server::Notification::new("window/showMessage".to_string(), params);
+                    if !send(notification) {
+                        return;
+                    }
+                }
+            }
+        }
+    });
+    sender
+}
+
+/// One-line account of a document's errors and warnings, or `None` if it has neither.
+fn diagnostic_summary(uri: &Url, diagnostics: &[Diagnostic]) -> Option<String> {
+    let count = |severity| {
+        diagnostics
+            .iter()
+            .filter(|d| d.severity == Some(severity))
+            .count()
+    };
+    let counts: Vec<String> = [
+        (count(DiagnosticSeverity::ERROR), "error"),
+        (count(DiagnosticSeverity::WARNING), "warning"),
+    ]
+    .into_iter()
+    .filter(|&(n, _)| n > 0)
+    .map(|(n, what)| format!("{} {}{}", n, what, if n == 1 { "" } else { "s" }))
+    .collect();
+    if counts.is_empty() {
+        return None;
+    }
+    let name = uri
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .unwrap_or(uri.as_str());
+    Some(format!("{}: {}", name, counts.join(", ")))
+}
+
 fn validate_document(
-    connection: &Connection,
     uri: &Url,
     text: &str,
-) -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
+    version: Option<i32>,
+) -> lsp_types::PublishDiagnosticsParams {
     let mut diagnostics = Vec::new();
+    let config = config::CONFIG.lock().unwrap().clone();
 
-    match stacky::Script::from_str(text) {
-        Ok(_) => {}
-        Err(errors) => {
-            let lines: Vec<&str> = text.lines().collect();
+    if text.len() > config.max_validate_bytes {
+        diagnostics.push(Diagnostic {
+            severity: Some(DiagnosticSeverity::INFORMATION),
+            source: Some("stacky".to_string()),
+            message: format!(
+                "Validation skipped: the document is larger than {} bytes (`maxValidateBytes`)",
+                config.max_validate_bytes
+            ),
+            ..Default::default()
+        });
+    } else {
+        if let Err(errors) = stacky::Script::from_str(text) {
             for err in errors.inner() {
-                let (start_line, start_char) = if err.pos.line == 0 {
-                    (0u32, 0u32)
-                } else {
-                    (
-                        (err.pos.line - 1) as u32,
-                        if err.pos.col == 0 {
-                            0u32
-                        } else {
-                            (err.pos.col - 1) as u32
-                        },
-                    )
-                };
-
-                let end_char = lines
-                    .get(start_line as usize)
-                    .map(|l| l.len() as u32)
-                    .unwrap_or(start_char + 1);
-
                 diagnostics.push(Diagnostic {
-                    range: Range {
-                        start: lsp_types::Position {
-                            line: start_line,
-                            character: start_char,
-                        },
-                        end: lsp_types::Position {
-                            line: start_line,
-                            character: end_char,
-                        },
-                    },
-                    severity: Some(DiagnosticSeverity::ERROR),
+                    range: error_range(text, &err.pos),
+                    severity: Some(error_severity(&err.kind)),
                     code: None,
                     code_description: None,
-                    source: Some(format!("stacky")),
+                    source: Some("stacky".to_string()),
                     message: err.kind.to_string(),
                     related_information: None,
                     tags: None,
@@ -544,46 +1185,399 @@ fn validate_document(
                 });
             }
         }
-    };
+        // the lints scan lines on their own, so they still apply to a document that doesn't
+        // parse; where they overlap the parser's errors, the sort and dedup below sort it out
+        diagnostics.extend(analysis::lint_document(uri, text, &config));
+    }
+
+    merge_diagnostics(&mut diagnostics);
+    for diagnostic in &mut diagnostics {
+        diagnostic.range = position::range_to_lsp(text, diagnostic.range);
+        for related in diagnostic.related_information.iter_mut().flatten() {
+            related.location.range = position::range_to_lsp(text, related.location.range);
+        }
+    }
 
-    let params = lsp_types::PublishDiagnosticsParams {
+    lsp_types::PublishDiagnosticsParams {
         uri: uri.clone(),
         diagnostics,
-        version: None,
+        version,
+    }
+}
+
+/// Orders diagnostics by position and, at one spot, most severe first, so the problems panel
+/// stays stable regardless of the order they were found in. Only a problem found twice with
+/// the same range and message, say by the parser and a lint, collapses into one squiggle.
+fn merge_diagnostics(diagnostics: &mut Vec<Diagnostic>) {
+    diagnostics.sort_by_key(|d| {
+        (
+            d.range.start,
+            d.severity.unwrap_or(DiagnosticSeverity::HINT),
+        )
+    });
+    let mut seen = HashSet::new();
+    diagnostics.retain(|d| seen.insert((d.range, d.message.clone())));
+}
+
+/// Range of the instruction a parse error points at: its command and operands, without any
+/// trailing comment. `stacky` only reports where the error starts, so a position that doesn't
+/// land on an instruction falls back to running to the end of its line.
+fn error_range(text: &str, pos: &stacky::Position) -> Range {
+    let line = pos.line.saturating_sub(1);
+    let col = pos.col.saturating_sub(1);
+    if let Some(inst) = analysis::instructions(text)
+        .into_iter()
+        .find(|inst| inst.line == line && inst.col <= col)
+    {
+        return inst.range();
+    }
+    let end = text.lines().nth(line).map_or(col + 1, str::len);
+    analysis::line_range(line, col, end.max(col))
+}
+
+/// Severity of an issue reported by `stacky`'s parser. It only reports invalid arguments and
+/// unknown commands, both of which stop the script from running; the other kinds come from
+/// running a script, and should a parse ever report one it stays an error until known to be
+/// harmless.
+fn error_severity(kind: &stacky::ErrorKind) -> DiagnosticSeverity {
+    match kind {
+        stacky::ErrorKind::InvalidArgument(_) | stacky::ErrorKind::UnknownCommand(_) => {
+            DiagnosticSeverity::ERROR
+        }
+        _ => DiagnosticSeverity::ERROR,
+    }
+}
+
+/// Describes the inline value under byte column `col` of `line`: its type as the parser
+/// reads it and, for numbers, the value that ends up on the stack.
+fn literal_hover(line: &str, col: usize) -> Option<String> {
+    let inst = analysis::instructions(line).into_iter().next()?;
+    let (_, literal) = inst
+        .values()
+        .into_iter()
+        .find(|(start, literal)| (*start..=start + literal.len()).contains(&col))?;
+    let value_type = match inst.name {
+        // the message is pushed as text whatever it looks like
+        "assert" | "error" if inst.values().len() == 1 => ValueType::String,
+        _ => ValueType::of_literal(literal),
+    };
+    let value = match value_type {
+        ValueType::Int => analysis::parse_integer(literal)?.to_string(),
+        ValueType::Float => literal.parse::<f64>().ok()?.to_string(),
+        ValueType::String => literal
+            .strip_prefix('"')
+            .and_then(|s| s.strip_suffix('"'))
+            .unwrap_or(literal)
+            .to_string(),
+        ValueType::Bool | ValueType::Nil => literal.to_string(),
+        ValueType::Unknown => return None,
     };
+    Some(format!(
+        "```stacky\n{}\n```\n\n`{}` literal with value `{}`",
+        literal, value_type, value
+    ))
+}
+
+/// Returns hover documentation for the command under `position` (a byte column), or `None`
+/// when the position is past the end of the document or not on a known command.
+fn get_hover(document: &Document, position: lsp_types::Position) -> Option<lsp_types::Hover> {
+    let l = document.text.lines().nth(position.line as usize)?;
+    // determine cursor column and extract the token under cursor (better than split_whitespace)
+    let mut col = (position.character as usize).min(l.len());
+    while !l.is_char_boundary(col) {
+        col -= 1;
+    }
+    // words in a comment are prose, not commands
+    if col > analysis::strip_comment(l).len() {
+        return None;
+    }
+
+    // find start of word (search backward for whitespace)
+    let start = l[..col]
+        .rfind(|c: char| c.is_whitespace())
+        .map(|p| p + 1)
+        .unwrap_or(0);
+    // find end of word (search forward for whitespace)
+    let end = l[col..]
+        .find(|c: char| c.is_whitespace())
+        .map(|p| col + p)
+        .unwrap_or(l.len());
+
+    let mut token = l[start..end].trim_matches(|c: char| !c.is_alphanumeric() && c != '_');
+    // if token empty, try a fallback: split_whitespace and pick a non-empty
+    if token.is_empty() {
+        token = l.split_whitespace().next().unwrap_or("");
+    }
+
+    if l.split_whitespace().next() == Some("convert")
+        && start > 0
+        && commands::TYPES.contains(&token)
+    {
+        let md = format!(
+            "```stacky\nconvert {}\n```\n\nConverts the top of the stack to `{}`.\n\n{}",
+            token,
+            token,
+            commands::conversion_table(token)
+        );
+        return Some(lsp_types::Hover {
+            contents: lsp_types::HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: md,
+            }),
+            range: None,
+        });
+    }
+
+    if let Some(md) = literal_hover(l, col) {
+        return Some(lsp_types::Hover {
+            contents: lsp_types::HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: md,
+            }),
+            range: None,
+        });
+    }
 
-    let notification = lsp_server::Notification {
-        method: "textDocument/publishDiagnostics".to_string(),
-        params: serde_json::to_value(params)?,
+    let Some(command) = commands::find(token) else {
+        // only the command itself can be misspelled; other words are labels or values
+        let at_command = analysis::instructions(l)
+            .first()
+            .is_some_and(|inst| inst.col == start && inst.label().is_none());
+        if !at_command || token.is_empty() {
+            return None;
+        }
+        let md = match commands::closest(token) {
+            Some(suggestion) => format!(
+                "Unknown command `{}`. Did you mean `{}`?",
+                token, suggestion.name
+            ),
+            None => format!("Unknown command `{}`.", token),
+        };
+        return Some(lsp_types::Hover {
+            contents: lsp_types::HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: md,
+            }),
+            range: None,
+        });
+    };
+    if config::CONFIG.lock().unwrap().is_disabled(command.name) {
+        return Some(lsp_types::Hover {
+            contents: lsp_types::HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: format!("`{}` is not supported in this target.", command.name),
+            }),
+            range: None,
+        });
+    }
+    let display = if let Some(sig) = SIGNATURES.get(command.name) {
+        sig.to_string()
+    } else {
+        command.name.to_string()
     };
+    let mut md = format!(
+        "```stacky\n{}\n```\n\n{}\n\n---\n\n{}",
+        display,
+        command.description,
+        command.effect_table()
+    );
+    if let Some(notes) = NOTES.get(command.name) {
+        md.push_str(&format!("\n\n**Notes**\n\n{}", notes));
+    }
+    match document.index.command_counts.get(command.name) {
+        Some(1) => md.push_str("\n\nUsed once in this file."),
+        Some(&count) => md.push_str(&format!("\n\nUsed {} times in this file.", count)),
+        None => {}
+    }
+    Some(lsp_types::Hover {
+        contents: lsp_types::HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: md,
+        }),
+        range: None,
+    })
+}
 
-    connection
-        .sender
-        .send(Message::Notification(notification))?;
-    Ok(())
+/// Signature of the command on the cursor's line while the cursor is within its argument.
+/// `None` once the cursor leaves the instruction, moves into its comment or, for `convert`,
+/// past the single word it takes, which tells the client to close the popup.
+fn get_signature_help(
+    text: &str,
+    position: lsp_types::Position,
+    context: Option<&SignatureHelpContext>,
+) -> Option<SignatureHelp> {
+    let col = position.character as usize;
+    let line = text.lines().nth(position.line as usize)?;
+    let inst = analysis::instructions(line).into_iter().next()?;
+    if col <= inst.col + inst.name.len() || col > analysis::strip_comment(line).len() {
+        return None;
+    }
+    // a type name is one word; every other signature takes the rest of the line
+    let type_name = inst.arg.split_whitespace().next().unwrap_or("");
+    if inst.name == "convert" && !type_name.is_empty() && col > inst.arg_col + type_name.len() {
+        return None;
+    }
+    let sig = SIGNATURES.get(inst.name)?;
+    let command = commands::find(inst.name)?;
+    let parameter = sig.split_once(' ').map(|(_, p)| p).unwrap_or_default();
+    let signatures = vec![SignatureInformation {
+        label: sig.to_string(),
+        documentation: Some(Documentation::String(command.description.to_string())),
+        parameters: Some(vec![ParameterInformation {
+            label: ParameterLabel::Simple(parameter.to_string()),
+            documentation: PARAMETERS.get(inst.name).map(|doc| {
+                Documentation::MarkupContent(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value: doc.to_string(),
+                })
+            }),
+        }]),
+        active_parameter: Some(0),
+    }];
+    // keep the signature the client is showing when it asks again as the cursor moves
+    let active_signature = context
+        .filter(|context| context.is_retrigger)
+        .and_then(|context| context.active_signature_help.as_ref())
+        .and_then(|help| help.active_signature)
+        .filter(|&active| (active as usize) < signatures.len())
+        .unwrap_or(0);
+    Some(SignatureHelp {
+        signatures,
+        active_signature: Some(active_signature),
+        active_parameter: Some(0),
+    })
+}
+
+/// Completion item features the client has announced support for.
+struct CompletionSupport {
+    snippet: bool,
+    commit_characters: bool,
+    insert_replace: bool,
+}
+
+fn completion_item_support() -> CompletionSupport {
+    let capabilities = CLIENT_CAPABILITIES.lock().unwrap();
+    let item = capabilities
+        .text_document
+        .as_ref()
+        .and_then(|t| t.completion.as_ref())
+        .and_then(|c| c.completion_item.as_ref());
+    CompletionSupport {
+        snippet: item.and_then(|i| i.snippet_support).unwrap_or(false),
+        commit_characters: item
+            .and_then(|i| i.commit_characters_support)
+            .unwrap_or(false),
+        insert_replace: item.and_then(|i| i.insert_replace_support).unwrap_or(false),
+    }
+}
+
+/// Turns a signature such as `goto <label>` into the snippet `goto ${1:label}`.
+fn signature_snippet(signature: &str) -> String {
+    let mut index = 0;
+    signature
+        .split(' ')
+        .map(
+            |part| match part.strip_prefix('<').and_then(|p| p.strip_suffix('>')) {
+                Some(param) => {
+                    index += 1;
+                    format!("${{{}:{}}}", index, param)
+                }
+                None => part.to_string(),
+            },
+        )
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
-fn get_completions(_params: &CompletionParams) -> Vec<CompletionItem> {
-    let constants = vec!["true", "false", "nil"];
+/// Lines after a label's definition shown in its completion documentation.
+const LABEL_PREVIEW_LINES: usize = 3;
 
-    let line = _params.text_document_position.position.line as usize;
-    let col = _params.text_document_position.position.character as usize;
-    let text = LATEST_TEXT.lock().unwrap().clone();
+/// Ranks a command popping between `min` and `max` values against a stack of `depth`: 0 if it
+/// can take the whole stack, 2 if it would underflow, 1 otherwise.
+fn stack_fit(depth: usize, min: usize, max: usize) -> u8 {
+    if min > depth {
+        2
+    } else if depth == min || depth == max {
+        0
+    } else {
+        1
+    }
+}
 
-    let mut labels = Vec::new();
-    let mut locals = Vec::new();
-    for l in text.lines() {
-        let t = l.trim();
-        if t.ends_with(":") {
-            labels.push(t.trim_end_matches(":").to_string());
+/// The command that almost certainly comes next on `line`, judged from the instruction above
+/// it: `br` when that one leaves a boolean on top of the stack, or whatever always follows
+/// that command elsewhere in the document, if it has been seen at least twice.
+fn likely_command(instructions: &[analysis::Instruction], line: usize) -> Option<&'static str> {
+    let previous = instructions.iter().rev().find(|inst| inst.line < line)?;
+    if previous.label().is_some() || !analysis::falls_through(previous) {
+        return None;
+    }
+    let top =
+        stack::stack_after(instructions, previous.line).and_then(|stack| stack.types().next());
+    if top == Some(ValueType::Bool) {
+        return Some("br");
+    }
+    let mut followers = instructions
+        .windows(2)
+        .filter(|pair| pair[0].name == previous.name && pair[1].line != line)
+        .map(|pair| pair[1].name);
+    let first = followers.next()?;
+    let mut seen = 1;
+    for follower in followers {
+        if follower != first {
+            return None;
         }
-        if t.starts_with("store ") {
-            let name = t[6..].split_whitespace().next().unwrap_or("");
-            if !name.is_empty() {
-                locals.push(name.to_string());
+        seen += 1;
+    }
+    let command = commands::find(first)?;
+    (seen >= 2).then_some(command.name)
+}
+
+/// Keeps one item per label where names collide, e.g. a label called `add`, preferring
+/// what the document defines over built-in keywords over plain text.
+fn dedup_completions(items: Vec<CompletionItem>) -> Vec<CompletionItem> {
+    let specificity = |item: &CompletionItem| match item.kind {
+        Some(CompletionItemKind::VARIABLE | CompletionItemKind::FIELD) => 0,
+        Some(CompletionItemKind::KEYWORD) => 1,
+        _ => 2,
+    };
+    let mut result: Vec<CompletionItem> = Vec::with_capacity(items.len());
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for item in items {
+        match seen.get(&item.label) {
+            Some(&i) if specificity(&item) < specificity(&result[i]) => result[i] = item,
+            Some(_) => {}
+            None => {
+                seen.insert(item.label.clone(), result.len());
+                result.push(item);
             }
         }
     }
+    result
+}
+
+fn get_completions(document: &Document, params: &CompletionParams) -> CompletionList {
+    let Document { text, index, .. } = document;
+    let position = position::from_lsp(text, params.text_document_position.position);
+    // an explicit request gets everything; completion popping up while typing only gets
+    // what matches the word so far
+    let invoked = params
+        .context
+        .as_ref()
+        .is_none_or(|context| context.trigger_kind == CompletionTriggerKind::INVOKED);
+    let line = position.line as usize;
+    let col = position.character as usize;
+
+    // the current line is the one being edited, so anything it defines would only
+    // suggest the symbol back to itself
+    let elsewhere = |sites: &[(String, Range)]| -> Vec<String> {
+        sites
+            .iter()
+            .filter(|(_, range)| range.start.line as usize != line)
+            .map(|(name, _)| name.clone())
+            .collect()
+    };
+    let locals = elsewhere(&index.stores);
 
     let mut items = Vec::new();
 
@@ -592,8 +1586,22 @@ fn get_completions(_params: &CompletionParams) -> Vec<CompletionItem> {
         let linetext = lines[line];
         let prefix = &linetext[..col.min(linetext.len())];
 
-        if prefix.contains(';') {
-            return vec![];
+        if analysis::strip_comment(prefix).len() < prefix.len() {
+            return CompletionList::default();
+        }
+        // a finished label definition takes nothing after it
+        if prefix.trim_end().ends_with(':') {
+            return CompletionList::default();
+        }
+        // neither does a command without arguments; one instruction per line means nothing
+        // else can follow either, and the parser already reports any argument given
+        if prefix
+            .split_whitespace()
+            .next()
+            .is_some_and(|name| commands::NO_ARGUMENTS.contains(&name))
+            && prefix.trim_start().contains(char::is_whitespace)
+        {
+            return CompletionList::default();
         }
 
         if prefix.trim().is_empty() {
@@ -604,32 +1612,112 @@ fn get_completions(_params: &CompletionParams) -> Vec<CompletionItem> {
     } else {
         true
     };
+    // a word at the head of the line that no command starts with can only be a label being
+    // defined, so the command list would just get in the way
+    let defining_label = is_line_head
+        && lines.get(line).is_some_and(|linetext| {
+            let word = linetext[..col.min(linetext.len())].trim();
+            !word.is_empty() && !COMMANDS.iter().any(|c| c.name.starts_with(word))
+        });
 
-    if is_line_head {
-        for (name, _description, _effect) in COMMANDS.iter() {
+    let support = completion_item_support();
+    if is_line_head && (invoked || !defining_label) {
+        // rank commands by how often the document already uses them, then alphabetically
+        let instructions = analysis::instructions(text);
+        // with a known depth, commands that take exactly what is on the stack come first and
+        // ones that would run out of values last
+        let depth = stack::depth_at_line(&instructions, &index.stack_depths, line);
+        let fit = |command: &commands::Command| match (depth, command.pop) {
+            (Some(depth), StackCount::Exact(pop)) => stack_fit(depth, pop, pop),
+            (Some(depth), StackCount::Either(a, b)) => stack_fit(depth, a.min(b), a.max(b)),
+            _ => 1,
+        };
+        let likely = likely_command(&instructions, line);
+        let config = config::CONFIG.lock().unwrap().clone();
+        let mut ranked: Vec<&commands::Command> = COMMANDS
+            .iter()
+            .filter(|c| !config.is_disabled(c.name))
+            .collect();
+        ranked.sort_by_key(|c| {
+            (
+                fit(c),
+                std::cmp::Reverse(index.command_counts.get(c.name).copied()),
+                c.name,
+            )
+        });
+        for (rank, command) in ranked.into_iter().enumerate() {
+            // commands taking an argument expand to a snippet with a placeholder for it
+            let snippet = SIGNATURES
+                .get(command.name)
+                .filter(|_| support.snippet)
+                .map(|sig| signature_snippet(sig));
             items.push(CompletionItem {
-                label: name.to_string(),
+                label: command.name.to_string(),
                 kind: Some(CompletionItemKind::KEYWORD),
                 detail: Some("command".to_string()),
                 documentation: None,
+                insert_text_format: snippet.as_ref().map(|_| InsertTextFormat::SNIPPET),
+                insert_text: snippet,
+                commit_characters: support.commit_characters.then(|| vec![" ".to_string()]),
+                sort_text: Some(format!("{:03}", rank)),
+                preselect: (likely == Some(command.name)).then_some(true),
                 ..Default::default()
             });
         }
     }
 
-    let mut show_constants = false;
-    if line < lines.len() {
-        let linetext = lines[line];
-        let prefix = &linetext[..col.min(linetext.len())];
-        if let Some(prev) = prefix.split_whitespace().last() {
-            if prev == "push" {
-                show_constants = true;
-            }
-        }
+    // an empty file can start from a whole program, also once its first word is typed
+    let empty = text
+        .lines()
+        .enumerate()
+        .all(|(i, l)| i == line || l.trim().is_empty());
+    if empty && is_line_head {
+        items.push(CompletionItem {
+            label: "program skeleton".to_string(),
+            kind: Some(CompletionItemKind::SNIPPET),
+            detail: Some("main: ... exit 0".to_string()),
+            documentation: Some(Documentation::MarkupContent(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: format!("```stacky\n{}```", SKELETON),
+            })),
+            filter_text: Some("main".to_string()),
+            insert_text: Some(SKELETON.to_string()),
+            insert_text_format: Some(InsertTextFormat::PLAIN_TEXT),
+            ..Default::default()
+        });
+    }
+
+    if defining_label {
+        let word = lines[line][..col.min(lines[line].len())].trim();
+        let (insert_text, insert_text_format) = if support.snippet {
+            (format!("${{1:{}}}:", word), InsertTextFormat::SNIPPET)
+        } else {
+            (format!("{}:", word), InsertTextFormat::PLAIN_TEXT)
+        };
+        items.push(CompletionItem {
+            label: "new label".to_string(),
+            kind: Some(CompletionItemKind::SNIPPET),
+            detail: Some(format!("{}:", word)),
+            documentation: Some(Documentation::String(
+                "Define a label that `goto` and `br` can jump to.".to_string(),
+            )),
+            // match against what has been typed, which never looks like "new label"
+            filter_text: Some(word.to_string()),
+            insert_text: Some(insert_text),
+            insert_text_format: Some(insert_text_format),
+            ..Default::default()
+        });
     }
 
-    if show_constants {
-        for name in &constants {
+    // constants are offered anywhere among the inline values of a command that takes them
+    let constants = lines
+        .get(line)
+        .map(|linetext| &linetext[..col.min(linetext.len())])
+        .filter(|prefix| prefix.trim_start().contains(char::is_whitespace))
+        .and_then(|prefix| prefix.split_whitespace().next())
+        .and_then(|name| commands::CONSTANTS.get(name));
+    if let Some(constants) = constants {
+        for name in constants.iter() {
             items.push(CompletionItem {
                 label: name.to_string(),
                 kind: Some(CompletionItemKind::KEYWORD),
@@ -641,14 +1729,14 @@ fn get_completions(_params: &CompletionParams) -> Vec<CompletionItem> {
     }
 
     // type suggestions for convert
-    let type_names = vec!["string", "int", "float", "bool", "nil"];
+    let type_names = commands::TYPES;
     if line < lines.len() {
         let linetext = lines[line];
         let prefix = &linetext[..col.min(linetext.len())];
         let parts: Vec<&str> = prefix.split_whitespace().collect();
-        if parts.len() >= 1 && parts[0] == "convert" {
+        if !parts.is_empty() && parts[0] == "convert" {
             // if cursor is after 'convert' and we are typing args, suggest types
-            for t in &type_names {
+            for t in type_names {
                 items.push(CompletionItem {
                     label: t.to_string(),
                     kind: Some(CompletionItemKind::KEYWORD),
@@ -665,23 +1753,38 @@ fn get_completions(_params: &CompletionParams) -> Vec<CompletionItem> {
     if line < lines.len() {
         let linetext = lines[line];
         let prefix = &linetext[..col.min(linetext.len())];
-        if let Some(prev) = prefix.split_whitespace().last() {
-            if prev == "goto" || prev == "br" {
-                show_labels = true;
-            }
-            if prev == "load" || prev == "store" {
-                show_locals = true;
-            }
+        // the whole rest of the line is the name, so this holds right up to its end
+        let in_argument = prefix.trim_start().contains(char::is_whitespace);
+        match prefix.split_whitespace().next() {
+            Some("goto" | "br") if in_argument => show_labels = true,
+            Some("load" | "store") if in_argument => show_locals = true,
+            _ => {}
         }
     }
 
     if show_labels {
-        for label in &labels {
+        for (label, range) in index
+            .labels
+            .iter()
+            .filter(|(_, range)| range.start.line as usize != line)
+        {
+            // the first few lines of the labelled block tell similar names apart
+            let definition = range.start.line as usize;
+            let preview = lines
+                .iter()
+                .skip(definition)
+                .take(LABEL_PREVIEW_LINES + 1)
+                .copied()
+                .collect::<Vec<_>>()
+                .join("\n");
             items.push(CompletionItem {
                 label: label.clone(),
                 kind: Some(CompletionItemKind::FIELD),
-                detail: Some("label".to_string()),
-                documentation: None,
+                detail: Some(format!("label (line {})", definition + 1)),
+                documentation: Some(Documentation::MarkupContent(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value: format!("```stacky\n{}\n```", preview),
+                })),
                 ..Default::default()
             });
         }
@@ -699,5 +1802,549 @@ fn get_completions(_params: &CompletionParams) -> Vec<CompletionItem> {
         }
     }
 
-    items
+    let mut items = dedup_completions(items);
+
+    // replace the whole word under the cursor on accept, not just the part before it, so
+    // accepting in the middle of `prnt` doesn't leave the tail behind
+    let linetext = lines.get(line).copied().unwrap_or("");
+    let col = col.min(linetext.len());
+    let word_start = linetext[..col]
+        .rfind(char::is_whitespace)
+        .map(|p| p + 1)
+        .unwrap_or(0);
+    let word_end = linetext[col..]
+        .find(char::is_whitespace)
+        .map(|p| col + p)
+        .unwrap_or(linetext.len());
+    let range = |start: usize, end: usize| {
+        position::range_to_lsp(text, analysis::line_range(line, start, end))
+    };
+    let (insert, replace) = (range(word_start, col), range(word_start, word_end));
+    // a list narrowed to the typed word has to be asked for again as the word grows
+    let typed = &linetext[word_start..col];
+    let matches_typed = |item: &CompletionItem| {
+        item.filter_text
+            .as_deref()
+            .unwrap_or(&item.label)
+            .starts_with(typed)
+    };
+    let mut is_incomplete = !invoked && !typed.is_empty();
+    if is_incomplete {
+        items.retain(matches_typed);
+    }
+    // a capped list is incomplete too, so the client asks again with more typed
+    let limit = config::CONFIG.lock().unwrap().max_completion_items;
+    if items.len() > limit {
+        items.sort_by_key(|item| {
+            (
+                !matches_typed(item),
+                item.kind != Some(CompletionItemKind::KEYWORD),
+            )
+        });
+        items.truncate(limit);
+        is_incomplete = true;
+    }
+    for item in &mut items {
+        let new_text = item
+            .insert_text
+            .take()
+            .unwrap_or_else(|| item.label.clone());
+        item.text_edit = Some(if support.insert_replace {
+            CompletionTextEdit::InsertAndReplace(InsertReplaceEdit {
+                new_text,
+                insert,
+                replace,
+            })
+        } else {
+            CompletionTextEdit::Edit(TextEdit {
+                range: replace,
+                new_text,
+            })
+        });
+    }
+
+    CompletionList {
+        is_incomplete,
+        items,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_server::Notification;
+    use serde_json::{Value, json};
+    use std::sync::PoisonError;
+    use std::time::Duration;
+
+    /// The server keeps its documents and settings in globals, so servers under test take
+    /// turns.
+    static SERVER_LOCK: Mutex<()> = Mutex::new(());
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// The client end of an in-memory connection to a running server.
+    struct Client {
+        connection: Connection,
+        next_id: i32,
+        /// notifications that arrived while waiting for a response
+        notifications: Vec<Notification>,
+    }
+
+    impl Client {
+        fn request(&mut self, method: &str, params: Value) -> Response {
+            self.next_id += 1;
+            let id = RequestId::from(self.next_id);
+            let request = Request::new(id.clone(), method.to_string(), params);
+            self.connection.sender.send(request.into()).unwrap();
+            loop {
+                match self.connection.receiver.recv_timeout(TIMEOUT).unwrap() {
+                    Message::Response(resp) if resp.id == id => return resp,
+                    Message::Notification(notification) => self.notifications.push(notification),
+                    _ => {}
+                }
+            }
+        }
+
+        /// The result of a request that must succeed.
+        fn result(&mut self, method: &str, params: Value) -> Value {
+            let resp = self.request(method, params);
+            assert!(resp.error.is_none(), "{} failed: {:?}", method, resp.error);
+            resp.result.unwrap_or(Value::Null)
+        }
+
+        fn notify(&self, method: &str, params: Value) {
+            let notification = Notification::new(method.to_string(), params);
+            self.connection.sender.send(notification.into()).unwrap();
+        }
+
+        fn open(&self, uri: &str, text: &str) {
+            self.notify(
+                "textDocument/didOpen",
+                json!({
+                    "textDocument": {
+                        "uri": uri,
+                        "languageId": "stacky",
+                        "version": 1,
+                        "text": text,
+                    }
+                }),
+            );
+        }
+
+        /// Labels of the completion items offered at a position.
+        fn completion_labels(&mut self, uri: &str, line: u32, character: u32) -> Vec<String> {
+            let result = self.result("textDocument/completion", position(uri, line, character));
+            let list: CompletionList = serde_json::from_value(result).unwrap();
+            list.items.into_iter().map(|item| item.label).collect()
+        }
+
+        /// The next diagnostics published for `uri`.
+        fn diagnostics(&mut self, uri: &str) -> Vec<Diagnostic> {
+            let is_for_uri = |notification: &Notification| {
+                notification.method == "textDocument/publishDiagnostics"
+                    && notification.params["uri"] == uri
+            };
+            let notification = match self.notifications.iter().position(is_for_uri) {
+                Some(i) => self.notifications.remove(i),
+                None => loop {
+                    match self.connection.receiver.recv_timeout(TIMEOUT).unwrap() {
+                        Message::Notification(n) if is_for_uri(&n) => break n,
+                        Message::Notification(n) => self.notifications.push(n),
+                        _ => {}
+                    }
+                },
+            };
+            serde_json::from_value(notification.params["diagnostics"].clone()).unwrap()
+        }
+    }
+
+    fn position(uri: &str, line: u32, character: u32) -> Value {
+        json!({
+            "textDocument": { "uri": uri },
+            "position": { "line": line, "character": character },
+        })
+    }
+
+    /// Runs a server with default settings and no documents on an in-memory connection,
+    /// initializes it, hands the client to `f` and shuts the server down again.
+    fn with_server(f: impl FnOnce(&mut Client)) {
+        let _turn = SERVER_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        *config::CONFIG.lock().unwrap() = config::Config::default();
+        DOCUMENTS.lock().unwrap().clear();
+
+        let (server, connection) = Connection::memory();
+        let thread = std::thread::spawn(move || run(&server).map_err(|err| err.to_string()));
+        let mut client = Client {
+            connection,
+            next_id: 0,
+            notifications: Vec::new(),
+        };
+        client.result("initialize", json!({ "capabilities": {} }));
+        client.notify("initialized", json!({}));
+
+        f(&mut client);
+
+        client.result("shutdown", Value::Null);
+        client.notify("exit", Value::Null);
+        thread.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn hover_after_open() {
+        with_server(|client| {
+            let uri = "file:///test/hover.stacky";
+            client.open(uri, "main:\n    push 1\n    println\n");
+            assert_eq!(client.diagnostics(uri), Vec::new());
+            let hover = client.result("textDocument/hover", position(uri, 1, 5));
+            let contents = hover["contents"]["value"].as_str().unwrap();
+            assert!(contents.contains("push"), "{}", contents);
+        });
+    }
+
+    #[test]
+    fn diagnostics_sorted_by_position() {
+        with_server(|client| {
+            let uri = "file:///test/sorted.stacky";
+            // the parser reports first, then the load lint, then the undefined-label lint
+            client.open(uri, "main:\n    goto nowhere\n    load x\n    nop 1\n");
+            let diagnostics = client.diagnostics(uri);
+            let found: Vec<(u32, &str)> = diagnostics
+                .iter()
+                .map(|d| (d.range.start.line, d.source.as_deref().unwrap()))
+                .collect();
+            assert_eq!(
+                found,
+                vec![
+                    (1, analysis::LINT_SOURCE),
+                    (2, analysis::LINT_SOURCE),
+                    (3, "stacky"),
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn hover_past_end_of_document() {
+        with_server(|client| {
+            let uri = "file:///test/eof.stacky";
+            client.open(uri, "main:\n    push 1\n");
+            assert_eq!(
+                client.result("textDocument/hover", position(uri, 10, 0)),
+                Value::Null
+            );
+            assert_eq!(
+                client.result("textDocument/hover", position(uri, 1, 40)),
+                Value::Null
+            );
+        });
+    }
+
+    #[test]
+    fn requests_for_unopened_document() {
+        with_server(|client| {
+            let uri = "file:///test/never-opened.stacky";
+            let at = position(uri, 0, 0);
+            let document = json!({ "textDocument": { "uri": uri } });
+            let requests = [
+                ("textDocument/hover", at.clone()),
+                ("textDocument/completion", at.clone()),
+                ("textDocument/signatureHelp", at.clone()),
+                ("textDocument/definition", at.clone()),
+                ("textDocument/prepareRename", at.clone()),
+                ("textDocument/documentSymbol", document.clone()),
+                ("textDocument/foldingRange", document.clone()),
+                ("textDocument/semanticTokens/full", document),
+            ];
+            for (method, params) in requests {
+                assert_eq!(client.result(method, params), Value::Null, "{}", method);
+            }
+        });
+    }
+
+    #[test]
+    fn configuration_change_clears_lints() {
+        with_server(|client| {
+            let settings = json!({ "settings": { "disabledCommands": ["println"] } });
+            client.notify("workspace/didChangeConfiguration", settings);
+            let uris = ["file:///test/first.stacky", "file:///test/second.stacky"];
+            for uri in uris {
+                client.open(uri, "main:\n    push \"hi\"\n    println\n    exit\n");
+                assert_eq!(client.diagnostics(uri).len(), 1);
+            }
+
+            let settings = json!({ "settings": { "disabledCommands": [] } });
+            client.notify("workspace/didChangeConfiguration", settings);
+            for uri in uris {
+                assert_eq!(client.diagnostics(uri), Vec::new());
+            }
+        });
+    }
+
+    #[test]
+    fn completion_of_label_and_variable_with_same_name() {
+        with_server(|client| {
+            let uri = "file:///test/same-name.stacky";
+            let text = "count:\n    push 1\n    store count\n    goto \n    load \ncount:\n";
+            client.open(uri, text);
+            for (line, kind) in [
+                (3, CompletionItemKind::FIELD),
+                (4, CompletionItemKind::VARIABLE),
+            ] {
+                let result = client.result("textDocument/completion", position(uri, line, 9));
+                let list: CompletionList = serde_json::from_value(result).unwrap();
+                let kinds: Vec<_> = list
+                    .items
+                    .iter()
+                    .filter(|item| item.label == "count")
+                    .map(|item| item.kind)
+                    .collect();
+                assert_eq!(kinds, vec![Some(kind)], "line {}", line);
+            }
+        });
+    }
+
+    #[test]
+    fn requests_at_end_of_line_and_file() {
+        with_server(|client| {
+            let uri = "file:///test/ends.stacky";
+            client.open(uri, "main:\n    push 1\n    goto \n    push \n    exit");
+            let hover_text =
+                |hover: Value| hover["contents"]["value"].as_str().unwrap().to_string();
+
+            // the end of a line, one past it, and the end of lines left open for an operand
+            let hover = client.result("textDocument/hover", position(uri, 1, 10));
+            assert!(hover_text(hover).contains("`int` literal with value `1`"));
+            assert_eq!(
+                client.result("textDocument/hover", position(uri, 1, 11)),
+                Value::Null
+            );
+            assert!(
+                client
+                    .completion_labels(uri, 2, 9)
+                    .contains(&"main".to_string())
+            );
+            assert!(
+                client
+                    .completion_labels(uri, 3, 9)
+                    .contains(&"nil".to_string())
+            );
+
+            // the end of the file, one past it and the line below it
+            let hover = client.result("textDocument/hover", position(uri, 4, 8));
+            assert!(hover_text(hover).contains("Exit the program"));
+            for (line, character) in [(4, 8), (4, 9), (5, 0)] {
+                let labels = client.completion_labels(uri, line, character);
+                assert!(
+                    labels.contains(&"exit".to_string()),
+                    "{}:{}",
+                    line,
+                    character
+                );
+            }
+            for (line, character) in [(4, 9), (5, 0)] {
+                assert_eq!(
+                    client.result("textDocument/hover", position(uri, line, character)),
+                    Value::Null
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn parser_error_and_lint_on_same_line() {
+        with_server(|client| {
+            let uri = "file:///test/overlap.stacky";
+            // an unterminated string the parser rejects, with a backslash the lints warn about
+            client.open(uri, "main:\n    push \"a\\n\n    exit\n");
+            let diagnostics = client.diagnostics(uri);
+            let found: Vec<_> = diagnostics
+                .iter()
+                .map(|d| (d.range.start.line, d.source.as_deref(), d.severity))
+                .collect();
+            assert_eq!(
+                found,
+                vec![
+                    (1, Some("stacky"), Some(DiagnosticSeverity::ERROR)),
+                    (
+                        1,
+                        Some(analysis::LINT_SOURCE),
+                        Some(DiagnosticSeverity::WARNING)
+                    ),
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn merge_overlapping_diagnostics() {
+        let range = Range::new(
+            lsp_types::Position::new(1, 4),
+            lsp_types::Position::new(1, 10),
+        );
+        let diagnostic = |severity, message: &str| Diagnostic {
+            range,
+            severity: Some(severity),
+            message: message.to_string(),
+            ..Default::default()
+        };
+        let mut diagnostics = vec![
+            diagnostic(DiagnosticSeverity::WARNING, "from a lint"),
+            diagnostic(DiagnosticSeverity::ERROR, "from the parser"),
+            diagnostic(DiagnosticSeverity::ERROR, "from the parser"),
+        ];
+        merge_diagnostics(&mut diagnostics);
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.severity.unwrap(), d.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (DiagnosticSeverity::ERROR, "from the parser"),
+                (DiagnosticSeverity::WARNING, "from a lint"),
+            ]
+        );
+    }
+
+    #[test]
+    fn indented_label_definition_and_references() {
+        with_server(|client| {
+            let uri = "file:///test/indented.stacky";
+            client.open(uri, "main:\n  loop:\n    push 1\n    goto loop\n");
+            let definition = client.result("textDocument/definition", position(uri, 3, 10));
+            let location: Location = serde_json::from_value(definition).unwrap();
+            assert_eq!(location.range.start, lsp_types::Position::new(1, 2));
+
+            let mut params = position(uri, 3, 10);
+            params["context"] = json!({ "includeDeclaration": true });
+            let references = client.result("textDocument/references", params);
+            let locations: Vec<Location> = serde_json::from_value(references).unwrap();
+            let starts: Vec<_> = locations.iter().map(|l| l.range.start).collect();
+            assert_eq!(
+                starts,
+                vec![
+                    lsp_types::Position::new(1, 2),
+                    lsp_types::Position::new(3, 9)
+                ]
+            );
+
+            let symbols = client.result("workspace/symbol", json!({ "query": "loop" }));
+            assert_eq!(symbols[0]["name"], "loop");
+            assert_eq!(
+                symbols[0]["location"]["range"]["start"],
+                json!({ "line": 1, "character": 2 })
+            );
+
+            let document = json!({ "textDocument": { "uri": uri } });
+            let outline = client.result("textDocument/documentSymbol", document);
+            assert_eq!(outline[1]["name"], "loop");
+            assert_eq!(
+                outline[1]["range"]["start"],
+                json!({ "line": 1, "character": 2 })
+            );
+            assert_eq!(
+                outline[1]["selectionRange"]["start"],
+                json!({ "line": 1, "character": 2 })
+            );
+        });
+    }
+
+    #[test]
+    fn document_symbols_streamed_in_chunks() {
+        with_server(|client| {
+            let uri = "file:///test/outline.stacky";
+            let text: String = (0..150)
+                .map(|i| format!("label{}:\n    nop\n", i))
+                .collect();
+            client.open(uri, &text);
+
+            let document = json!({ "textDocument": { "uri": uri } });
+            let outline = client.result("textDocument/documentSymbol", document.clone());
+            assert_eq!(outline.as_array().unwrap().len(), 150);
+
+            let mut params = document;
+            params["partialResultToken"] = json!("outline");
+            let result = client.result("textDocument/documentSymbol", params);
+            assert_eq!(result, json!([]));
+            let chunks: Vec<usize> = client
+                .notifications
+                .iter()
+                .filter(|n| n.method == "$/progress" && n.params["token"] == "outline")
+                .map(|n| n.params["value"].as_array().unwrap().len())
+                .collect();
+            assert_eq!(chunks, vec![100, 50]);
+        });
+    }
+
+    #[test]
+    fn no_completion_after_command_without_arguments() {
+        with_server(|client| {
+            let uri = "file:///test/no-arguments.stacky";
+            client.open(uri, "main:\n    dup \n    nop \n    push \n    exit\n");
+            assert_eq!(client.completion_labels(uri, 1, 8), Vec::<String>::new());
+            assert_eq!(client.completion_labels(uri, 2, 8), Vec::<String>::new());
+            let after_push = client.completion_labels(uri, 3, 9);
+            for constant in ["true", "false", "nil"] {
+                assert!(
+                    after_push.iter().any(|label| label == constant),
+                    "{}",
+                    constant
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn hover_reads_unopened_stacky_file_without_tracking_it() {
+        let dir = std::env::temp_dir().join(format!("stacky-lsp-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let text = "main:\n    push 1\n";
+        std::fs::write(dir.join("unopened.stacky"), text).unwrap();
+        std::fs::write(dir.join("unopened.txt"), text).unwrap();
+        let stacky = Url::from_file_path(dir.join("unopened.stacky")).unwrap();
+        let other = Url::from_file_path(dir.join("unopened.txt")).unwrap();
+
+        with_server(|client| {
+            let hover = client.result("textDocument/hover", position(stacky.as_str(), 1, 5));
+            assert!(
+                hover["contents"]["value"]
+                    .as_str()
+                    .unwrap()
+                    .contains("push")
+            );
+            let hover = client.result("textDocument/hover", position(other.as_str(), 1, 5));
+            assert_eq!(hover, Value::Null);
+            assert!(DOCUMENTS.lock().unwrap().is_empty());
+        });
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn initialize_unstored_variable_at_entry_label() {
+        with_server(|client| {
+            let uri = "file:///test/unstored.stacky";
+            client.open(uri, "; counts to nothing\nmain:\n    load x\n    exit 0\n");
+            let diagnostics = client.diagnostics(uri);
+            let result = client.result(
+                "textDocument/codeAction",
+                json!({
+                    "textDocument": { "uri": uri },
+                    "range": { "start": { "line": 2, "character": 9 }, "end": { "line": 2, "character": 9 } },
+                    "context": { "diagnostics": diagnostics, "only": ["quickfix"] },
+                }),
+            );
+            let action = result
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|action| action["title"].as_str().unwrap().starts_with("Initialize"))
+                .unwrap();
+            let edit: TextEdit =
+                serde_json::from_value(action["edit"]["changes"][uri][0].clone()).unwrap();
+            assert_eq!(edit.range.start, lsp_types::Position::new(2, 0));
+            assert_eq!(edit.range.end, lsp_types::Position::new(2, 0));
+            assert_eq!(edit.new_text, "    push nil\n    store x\n");
+        });
+    }
 }
@@ -0,0 +1,130 @@
+use lsp_types::{ClientCapabilities, Position, PositionEncodingKind, Range};
+use once_cell::sync::Lazy;
+use std::sync::Mutex;
+
+/// Whether positions exchanged with the client count UTF-8 bytes. Otherwise they count
+/// UTF-16 code units, which the protocol mandates when the client doesn't say.
+static UTF8_POSITIONS: Lazy<Mutex<bool>> = Lazy::new(|| Mutex::new(false));
+
+/// Picks the position encoding from the client's offer, preferring UTF-8 since that is how
+/// the server indexes lines. A client that lists no encodings gets UTF-16.
+pub fn negotiate(capabilities: &ClientCapabilities) -> PositionEncodingKind {
+    let utf8 = capabilities
+        .general
+        .as_ref()
+        .and_then(|g| g.position_encodings.as_ref())
+        .is_some_and(|encodings| encodings.contains(&PositionEncodingKind::UTF8));
+    *UTF8_POSITIONS.lock().unwrap() = utf8;
+    if utf8 {
+        PositionEncodingKind::UTF8
+    } else {
+        PositionEncodingKind::UTF16
+    }
+}
+
+/// Converts a client column into a byte offset within `line`, clamped to the line's end.
+pub fn to_byte_offset(line: &str, character: u32) -> usize {
+    let character = character as usize;
+    if *UTF8_POSITIONS.lock().unwrap() || line.is_ascii() {
+        let mut offset = character.min(line.len());
+        while !line.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        return offset;
+    }
+    let mut units = 0;
+    for (offset, c) in line.char_indices() {
+        if units >= character {
+            return offset;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+/// Converts a byte offset within `line` into a client column.
+pub fn to_character(line: &str, offset: usize) -> u32 {
+    let offset = offset.min(line.len());
+    if *UTF8_POSITIONS.lock().unwrap() || line.is_ascii() {
+        return offset as u32;
+    }
+    line[..offset].chars().map(char::len_utf16).sum::<usize>() as u32
+}
+
+/// Whether a client position lies past the end of its line, or below the last line.
+pub fn past_line_end(text: &str, position: Position) -> bool {
+    match text.lines().nth(position.line as usize) {
+        Some(line) => position.character > to_character(line, line.len()),
+        None => true,
+    }
+}
+
+/// Maps a client position to one whose `character` is a byte offset into the line.
+pub fn from_lsp(text: &str, position: Position) -> Position {
+    let line = text.lines().nth(position.line as usize).unwrap_or("");
+    Position {
+        line: position.line,
+        character: to_byte_offset(line, position.character) as u32,
+    }
+}
+
+/// Converts a client position into a byte offset into `text`. A position past the end of its
+/// line is clamped to the line's end, and one past the last line to the end of the text.
+pub fn to_text_offset(text: &str, position: Position) -> usize {
+    let mut start = 0;
+    for _ in 0..position.line {
+        match text[start..].find('\n') {
+            Some(newline) => start += newline + 1,
+            None => return text.len(),
+        }
+    }
+    let rest = &text[start..];
+    let line = rest.split('\n').next().unwrap_or("");
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    start + to_byte_offset(line, position.character)
+}
+
+/// Maps a range with byte-offset columns to the client's position encoding.
+pub fn range_to_lsp(text: &str, range: Range) -> Range {
+    let column = |p: Position| {
+        let line = text.lines().nth(p.line as usize).unwrap_or("");
+        Position {
+            line: p.line,
+            character: to_character(line, p.character as usize),
+        }
+    };
+    Range {
+        start: column(range.start),
+        end: column(range.end),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_types::InitializeParams;
+
+    fn client_capabilities(params: serde_json::Value) -> ClientCapabilities {
+        serde_json::from_value::<InitializeParams>(params)
+            .unwrap()
+            .capabilities
+    }
+
+    #[test]
+    fn negotiate_encoding() {
+        let minimal = client_capabilities(serde_json::json!({
+            "processId": null,
+            "capabilities": {},
+        }));
+        assert_eq!(negotiate(&minimal), PositionEncodingKind::UTF16);
+
+        let offers_utf8 = client_capabilities(serde_json::json!({
+            "processId": null,
+            "capabilities": { "general": { "positionEncodings": ["utf-16", "utf-8"] } },
+        }));
+        assert_eq!(negotiate(&offers_utf8), PositionEncodingKind::UTF8);
+
+        // leave the default for whatever runs next
+        negotiate(&minimal);
+    }
+}
@@ -0,0 +1,82 @@
+use lsp_types::{Position, Range, SelectionRange};
+
+use crate::analysis::{self, Instruction};
+use crate::position;
+
+fn contains(range: &Range, position: Position) -> bool {
+    range.start <= position && position <= range.end
+}
+
+/// The single token of `inst` under `position`: the command or label name, one inline
+/// value, or an operand that names a variable, label or type.
+fn token(inst: &Instruction, position: Position) -> Option<Range> {
+    let name = analysis::line_range(inst.line, inst.col, inst.col + inst.name.len());
+    if contains(&name, position) {
+        return Some(inst.name_range());
+    }
+    let values = inst.values();
+    if values.is_empty() {
+        return Some(inst.arg_range()).filter(|range| contains(range, position));
+    }
+    values
+        .into_iter()
+        .map(|(col, value)| analysis::line_range(inst.line, col, col + value.len()))
+        .find(|range| contains(range, position))
+}
+
+/// From the label at or above `line` to the last instruction before the next label.
+fn label_block(instructions: &[Instruction], line: usize) -> Option<Range> {
+    let start = instructions
+        .iter()
+        .rposition(|inst| inst.line <= line && inst.label().is_some())?;
+    let end = instructions[start + 1..]
+        .iter()
+        .take_while(|inst| inst.label().is_none())
+        .last()
+        .unwrap_or(&instructions[start]);
+    Some(Range {
+        start: instructions[start].range().start,
+        end: end.range().end,
+    })
+}
+
+/// Ranges around `position`, innermost first: the token, the operands, the whole instruction
+/// and the label block, skipping any that don't contain the position or repeat the one before.
+fn ranges_at(instructions: &[Instruction], position: Position) -> Vec<Range> {
+    let mut ranges = Vec::new();
+    let line = position.line as usize;
+    if let Some(inst) = instructions.iter().find(|inst| inst.line == line) {
+        ranges.extend(token(inst, position));
+        if !inst.arg.is_empty() && inst.label().is_none() {
+            ranges.push(inst.arg_range());
+        }
+        ranges.push(inst.range());
+    }
+    ranges.extend(label_block(instructions, line));
+    ranges.retain(|range| contains(range, position));
+    ranges.dedup();
+    ranges
+}
+
+/// One chain of ranges for each of `positions`, given in the client's encoding. A position
+/// outside any instruction or label block gets an empty range where it is.
+pub fn selection_ranges(text: &str, positions: &[Position]) -> Vec<SelectionRange> {
+    let instructions = analysis::instructions(text);
+    positions
+        .iter()
+        .map(|&at| {
+            let position = position::from_lsp(text, at);
+            let mut selection: Option<SelectionRange> = None;
+            for range in ranges_at(&instructions, position).into_iter().rev() {
+                selection = Some(SelectionRange {
+                    range: position::range_to_lsp(text, range),
+                    parent: selection.map(Box::new),
+                });
+            }
+            selection.unwrap_or(SelectionRange {
+                range: Range::new(at, at),
+                parent: None,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,216 @@
+use lsp_types::{
+    SemanticToken, SemanticTokenModifier, SemanticTokenType, SemanticTokens, SemanticTokensDelta,
+    SemanticTokensEdit, SemanticTokensFullDeltaResult, SemanticTokensLegend, Url,
+};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::analysis::{self, Instruction};
+use crate::commands;
+use crate::position;
+use crate::stack::ValueType;
+
+/// Kinds of token, in the order of `TOKEN_TYPES`.
+#[derive(Clone, Copy)]
+enum TokenKind {
+    Command,
+    Label,
+    Variable,
+    Number,
+    String,
+    /// `true`, `false` and `nil`
+    Constant,
+    /// type name given to `convert`
+    Type,
+    Comment,
+}
+
+const TOKEN_TYPES: &[SemanticTokenType] = &[
+    SemanticTokenType::KEYWORD,
+    SemanticTokenType::FUNCTION,
+    SemanticTokenType::VARIABLE,
+    SemanticTokenType::NUMBER,
+    SemanticTokenType::STRING,
+    SemanticTokenType::ENUM_MEMBER,
+    SemanticTokenType::TYPE,
+    SemanticTokenType::COMMENT,
+];
+
+/// Tokens last sent for each document, along with the result id they were sent under.
+static LAST_RESULTS: Lazy<Mutex<HashMap<Url, SemanticTokens>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static NEXT_RESULT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Set on a label's definition, as opposed to the jumps to it.
+const DECLARATION: u32 = 1;
+
+pub fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: TOKEN_TYPES.to_vec(),
+        token_modifiers: vec![SemanticTokenModifier::DECLARATION],
+    }
+}
+
+/// A token on one line, with byte columns.
+struct Span {
+    line: usize,
+    start: usize,
+    end: usize,
+    kind: TokenKind,
+    modifiers: u32,
+}
+
+fn span(line: usize, start: usize, end: usize, kind: TokenKind) -> Span {
+    Span {
+        line,
+        start,
+        end,
+        kind,
+        modifiers: 0,
+    }
+}
+
+fn instruction_spans(inst: &Instruction, spans: &mut Vec<Span>) {
+    if let Some(label) = inst.label() {
+        spans.push(Span {
+            modifiers: DECLARATION,
+            ..span(
+                inst.line,
+                inst.col,
+                inst.col + label.len(),
+                TokenKind::Label,
+            )
+        });
+        return;
+    }
+    // an unknown command is left to the diagnostics rather than colored like a real one
+    if commands::find(inst.name).is_some() {
+        let end = inst.col + inst.name.len();
+        spans.push(span(inst.line, inst.col, end, TokenKind::Command));
+    }
+    if inst.arg.is_empty() {
+        return;
+    }
+    let operand = match inst.name {
+        "goto" | "br" => Some(TokenKind::Label),
+        "store" | "load" => Some(TokenKind::Variable),
+        "convert" => Some(TokenKind::Type),
+        "pop" => Some(TokenKind::Number),
+        _ => None,
+    };
+    if let Some(kind) = operand {
+        let end = inst.arg_col + inst.arg.len();
+        spans.push(span(inst.line, inst.arg_col, end, kind));
+        return;
+    }
+    for (col, literal) in inst.values() {
+        let kind = match ValueType::of_literal(literal) {
+            ValueType::Int | ValueType::Float => TokenKind::Number,
+            ValueType::String => TokenKind::String,
+            ValueType::Bool | ValueType::Nil => TokenKind::Constant,
+            ValueType::Unknown => continue,
+        };
+        spans.push(span(inst.line, col, col + literal.len(), kind));
+    }
+}
+
+/// Tokens on `lines`, encoded relative to one another as the protocol requires.
+pub fn tokens(text: &str, lines: RangeInclusive<usize>) -> Vec<SemanticToken> {
+    let text_lines: Vec<&str> = text.lines().collect();
+    let mut spans = Vec::new();
+    for inst in analysis::instructions(text) {
+        if lines.contains(&inst.line) {
+            instruction_spans(&inst, &mut spans);
+        }
+    }
+    for (line, raw) in text_lines.iter().enumerate() {
+        let code = analysis::strip_comment(raw);
+        if lines.contains(&line) && code.len() < raw.len() {
+            spans.push(span(line, code.len(), raw.len(), TokenKind::Comment));
+        }
+    }
+    spans.sort_by_key(|span| (span.line, span.start));
+
+    let mut tokens = Vec::with_capacity(spans.len());
+    let (mut previous_line, mut previous_start) = (0, 0);
+    for span in spans {
+        let line = text_lines[span.line];
+        let start = position::to_character(line, span.start);
+        let end = position::to_character(line, span.end);
+        let delta_line = (span.line - previous_line) as u32;
+        tokens.push(SemanticToken {
+            delta_line,
+            delta_start: if delta_line == 0 {
+                start - previous_start
+            } else {
+                start
+            },
+            length: end - start,
+            token_type: span.kind as u32,
+            token_modifiers_bitset: span.modifiers,
+        });
+        previous_line = span.line;
+        previous_start = start;
+    }
+    tokens
+}
+
+/// Tokens for the whole document, remembered under a fresh result id so that the next
+/// request can ask for just what changed.
+pub fn full(uri: &Url, text: &str) -> SemanticTokens {
+    let tokens = SemanticTokens {
+        result_id: Some(NEXT_RESULT_ID.fetch_add(1, Ordering::Relaxed).to_string()),
+        data: tokens(text, 0..=usize::MAX),
+    };
+    LAST_RESULTS
+        .lock()
+        .unwrap()
+        .insert(uri.clone(), tokens.clone());
+    tokens
+}
+
+/// The edit turning the tokens last sent under `previous_result_id` into the current ones, or
+/// all of them if those are no longer remembered.
+pub fn delta(uri: &Url, text: &str, previous_result_id: &str) -> SemanticTokensFullDeltaResult {
+    let previous = LAST_RESULTS.lock().unwrap().remove(uri);
+    let tokens = full(uri, text);
+    let Some(SemanticTokens { data: old, .. }) =
+        previous.filter(|previous| previous.result_id.as_deref() == Some(previous_result_id))
+    else {
+        return SemanticTokensFullDeltaResult::Tokens(tokens);
+    };
+    let new = &tokens.data;
+    // one edit replacing whatever lies between the unchanged start and end
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let deleted = old.len() - prefix - suffix;
+    let inserted = &new[prefix..new.len() - suffix];
+    let edits = if deleted == 0 && inserted.is_empty() {
+        Vec::new()
+    } else {
+        // offsets and counts are in integers, five to a token
+        vec![SemanticTokensEdit {
+            start: (prefix * 5) as u32,
+            delete_count: (deleted * 5) as u32,
+            data: Some(inserted.to_vec()),
+        }]
+    };
+    SemanticTokensFullDeltaResult::TokensDelta(SemanticTokensDelta {
+        result_id: tokens.result_id,
+        edits,
+    })
+}
+
+/// Drops what was remembered for a document that is no longer tracked.
+pub fn forget(uri: &Url) {
+    LAST_RESULTS.lock().unwrap().remove(uri);
+}
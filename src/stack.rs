@@ -0,0 +1,323 @@
+use serde::Serialize;
+use std::fmt;
+
+use crate::analysis::{self, Instruction};
+use crate::commands::{self, StackCount};
+
+/// Number of values on the stack before an instruction that is reachable from the entry.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct StackDepth {
+    /// zero-based line of the instruction
+    pub line: usize,
+    /// `None` when paths disagree or an instruction's effect depends on runtime values
+    pub depth: Option<usize>,
+    /// depth once the instruction has run, `None` under the same conditions
+    pub after: Option<usize>,
+}
+
+/// Type of a value on the stack, as far as it can be told without running the program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueType {
+    Int,
+    Float,
+    String,
+    Bool,
+    Nil,
+    Unknown,
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ValueType::Int => "int",
+            ValueType::Float => "float",
+            ValueType::String => "string",
+            ValueType::Bool => "bool",
+            ValueType::Nil => "nil",
+            ValueType::Unknown => "unknown",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl ValueType {
+    /// Type of an inline literal, following the parser's order of attempts.
+    pub fn of_literal(literal: &str) -> Self {
+        if analysis::parse_integer(literal).is_some() {
+            ValueType::Int
+        } else if literal.parse::<f64>().is_ok() {
+            ValueType::Float
+        } else if matches!(literal, "true" | "false") {
+            ValueType::Bool
+        } else if literal == "nil" {
+            ValueType::Nil
+        } else if literal.starts_with('"') {
+            ValueType::String
+        } else {
+            ValueType::Unknown
+        }
+    }
+
+    fn of_name(name: &str) -> Self {
+        match name {
+            "int" => ValueType::Int,
+            "float" => ValueType::Float,
+            "string" => ValueType::String,
+            "bool" => ValueType::Bool,
+            "nil" => ValueType::Nil,
+            _ => ValueType::Unknown,
+        }
+    }
+}
+
+/// How an instruction changes the stack: values given inline are pushed first, then the
+/// command pops and pushes its own.
+struct Effect {
+    inline: usize,
+    pop: usize,
+    push: usize,
+}
+
+/// Effect of `inst` on a stack of `depth` values, or `None` if it depends on runtime values.
+fn effect(inst: &Instruction, depth: usize) -> Option<Effect> {
+    let inline = inst.values().len();
+    let (pop, push) = match inst.name {
+        _ if inst.label().is_some() => (0, 0),
+        "push" => (0, 0),
+        "pop" if inst.arg.is_empty() => (1, 0),
+        "pop" => (inst.arg.parse().ok()?, 0),
+        // printing does nothing on an empty stack
+        "print" | "println" => (usize::from(depth + inline > 0), 0),
+        // an inline message sits on top of the condition
+        "assert" if inline > 0 => (2, 0),
+        // every inline value is folded into one
+        "min" | "max" if inline > 1 => (inline, 1),
+        name => match commands::find(name).map(|c| (c.pop, c.push))? {
+            (StackCount::Exact(pop), StackCount::Exact(push)) => (pop, push),
+            _ => return None,
+        },
+    };
+    Some(Effect { inline, pop, push })
+}
+
+/// Depth after running `inst` on a stack of `depth` values, or `None` if it can't be known
+/// or the instruction would run out of values.
+fn step(inst: &Instruction, depth: usize) -> Option<usize> {
+    let effect = effect(inst, depth)?;
+    Some((depth + effect.inline).checked_sub(effect.pop)? + effect.push)
+}
+
+/// Runs `step` along every path from the first instruction, following jumps, and returns
+/// the state before each instruction: `None` if no path reaches it. Where paths meet their
+/// states are combined with `merge`, which must eventually stop changing a state.
+pub fn flow<S: Clone + PartialEq>(
+    instructions: &[Instruction],
+    entry: S,
+    step: impl Fn(&Instruction, &S) -> S,
+    merge: impl Fn(&S, &S) -> S,
+) -> Vec<Option<S>> {
+    let targets = analysis::label_targets(instructions);
+    let mut before: Vec<Option<S>> = vec![None; instructions.len()];
+    let mut pending = Vec::new();
+    if !instructions.is_empty() {
+        before[0] = Some(entry);
+        pending.push(0);
+    }
+    while let Some(i) = pending.pop() {
+        let Some(state) = &before[i] else {
+            continue;
+        };
+        let after = step(&instructions[i], state);
+        for next in analysis::successors(instructions, &targets, i) {
+            let merged = match &before[next] {
+                None => after.clone(),
+                Some(known) => merge(known, &after),
+            };
+            if before[next].as_ref() != Some(&merged) {
+                before[next] = Some(merged);
+                pending.push(next);
+            }
+        }
+    }
+    before
+}
+
+/// Tracks the stack depth along every path from the first instruction, following jumps.
+/// Instructions no path reaches are left out.
+pub fn stack_depths(instructions: &[Instruction]) -> Vec<StackDepth> {
+    let before = flow(
+        instructions,
+        Some(0),
+        |inst, depth: &Option<usize>| depth.and_then(|depth| step(inst, depth)),
+        // paths disagree; once unknown it stays that way, so this settles
+        |a, b| if a == b { *a } else { None },
+    );
+    instructions
+        .iter()
+        .zip(before)
+        .filter_map(|(inst, depth)| {
+            depth.map(|depth| StackDepth {
+                line: inst.line,
+                depth,
+                after: depth.and_then(|depth| step(inst, depth)),
+            })
+        })
+        .collect()
+}
+
+/// Depth of the stack for an instruction written on `line`, judged from the instruction
+/// above it. `None` if that one is unreachable, doesn't fall through or has an unknown depth.
+pub fn depth_at_line(
+    instructions: &[Instruction],
+    depths: &[StackDepth],
+    line: usize,
+) -> Option<usize> {
+    let Some(previous) = instructions.iter().rev().find(|inst| inst.line < line) else {
+        return Some(0);
+    };
+    if !analysis::falls_through(previous) {
+        return None;
+    }
+    depths
+        .iter()
+        .find(|depth| depth.line == previous.line)?
+        .after
+}
+
+/// Types kept for the values nearest the top; anything deeper is reported as unknown so
+/// that programs building deep stacks stay cheap to analyze.
+const TRACKED_TYPES: usize = 32;
+
+/// Stack as seen by the type simulation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stack {
+    pub depth: usize,
+    /// types of the topmost values, bottom first; at most `TRACKED_TYPES` of them
+    pub top: Vec<ValueType>,
+}
+
+impl Stack {
+    fn pop(&mut self) -> ValueType {
+        self.depth -= 1;
+        self.top.pop().unwrap_or(ValueType::Unknown)
+    }
+
+    fn push(&mut self, value: ValueType) {
+        self.depth += 1;
+        if self.top.len() == TRACKED_TYPES {
+            self.top.remove(0);
+        }
+        self.top.push(value);
+    }
+
+    /// Types from the top down, with untracked values as unknown.
+    pub fn types(&self) -> impl Iterator<Item = ValueType> + '_ {
+        self.top
+            .iter()
+            .rev()
+            .copied()
+            .chain(std::iter::repeat(ValueType::Unknown))
+            .take(self.depth)
+    }
+}
+
+/// Type a command pushes given the values it popped, bottom first.
+fn result_type(inst: &Instruction, operands: &[ValueType]) -> ValueType {
+    use ValueType::*;
+    let numeric = |a: ValueType, b: ValueType| match (a, b) {
+        (Int, Int) => Int,
+        (Int | Float, Int | Float) => Float,
+        _ => Unknown,
+    };
+    match (inst.name, operands) {
+        ("read", _) => String,
+        ("len" | "clz" | "ctz" | "shl" | "shr" | "rotl" | "rotr", _) => Int,
+        ("gt" | "lt" | "ge" | "le" | "eq" | "ne", _) => Bool,
+        ("convert", _) => ValueType::of_name(inst.arg),
+        ("dup", &[a]) => a,
+        ("add", &[String, String]) => String,
+        ("add" | "sub" | "mul" | "div" | "mod" | "pow", &[a, b]) => numeric(a, b),
+        // folded pairwise, so any float makes the result a float
+        ("min" | "max", values) => values.iter().copied().reduce(numeric).unwrap_or(Unknown),
+        ("and" | "or" | "xor", &[Bool, Bool]) => Bool,
+        ("and" | "or" | "xor", &[Int, Int]) => Int,
+        ("not", &[a @ (Bool | Int)]) => a,
+        ("neg" | "abs" | "sign" | "ceil" | "floor" | "trunc", &[a @ (Int | Float)]) => a,
+        (
+            "sqrt" | "sin" | "cos" | "tan" | "asin" | "acos" | "atan" | "sinh" | "cosh" | "tanh"
+            | "asinh" | "acosh" | "atanh" | "exp" | "log",
+            _,
+        ) => Float,
+        _ => Unknown,
+    }
+}
+
+fn step_types(inst: &Instruction, stack: &Stack) -> Option<Stack> {
+    let effect = effect(inst, stack.depth)?;
+    let mut stack = stack.clone();
+    for (_, literal) in inst.values() {
+        stack.push(ValueType::of_literal(literal));
+    }
+    if stack.depth < effect.pop {
+        return None;
+    }
+    let mut operands: Vec<ValueType> = (0..effect.pop).map(|_| stack.pop()).collect();
+    operands.reverse();
+    match (inst.name, effect.push) {
+        ("dup", _) => {
+            stack.push(operands[0]);
+            stack.push(operands[0]);
+        }
+        (_, push) => {
+            for _ in 0..push {
+                stack.push(result_type(inst, &operands));
+            }
+        }
+    }
+    Some(stack)
+}
+
+fn merge_types(a: &Option<Stack>, b: &Option<Stack>) -> Option<Stack> {
+    let (a, b) = (a.as_ref()?, b.as_ref()?);
+    if a.depth != b.depth {
+        return None;
+    }
+    // line the tracked types up from the top and forget the ones that disagree
+    let top: Vec<ValueType> = a
+        .top
+        .iter()
+        .rev()
+        .zip(b.top.iter().rev())
+        .map(|(x, y)| if x == y { *x } else { ValueType::Unknown })
+        .collect();
+    Some(Stack {
+        depth: a.depth,
+        top: top.into_iter().rev().collect(),
+    })
+}
+
+/// The stack before each instruction, with the type of each value where it can be told.
+/// `None` where the instruction isn't reached or the stack can't be tracked.
+pub fn stacks_before(instructions: &[Instruction]) -> Vec<Option<Stack>> {
+    let entry = Some(Stack {
+        depth: 0,
+        top: Vec::new(),
+    });
+    flow(
+        instructions,
+        entry,
+        |inst, stack: &Option<Stack>| stack.as_ref().and_then(|stack| step_types(inst, stack)),
+        merge_types,
+    )
+    .into_iter()
+    .map(Option::flatten)
+    .collect()
+}
+
+/// The stack once the instruction on `line` has run, with the type of each value where it
+/// can be told. `None` if the line isn't reached or the stack can't be tracked there.
+pub fn stack_after(instructions: &[Instruction], line: usize) -> Option<Stack> {
+    let index = instructions.iter().position(|inst| inst.line == line)?;
+    let stack = stacks_before(instructions).swap_remove(index)?;
+    step_types(&instructions[index], &stack)
+}
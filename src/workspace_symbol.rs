@@ -0,0 +1,118 @@
+use lsp_types::{
+    ClientCapabilities, Location, OneOf, Range, Url, WorkspaceLocation, WorkspaceSymbol,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::document::{self, DOCUMENTS, DocumentIndex, SymbolKind};
+use crate::position;
+
+/// Carried from a symbol to its resolve request, which looks up where it is defined.
+#[derive(Serialize, Deserialize)]
+struct SymbolData {
+    kind: SymbolKind,
+}
+
+/// Whether the client can fill in a symbol's range later through `workspaceSymbol/resolve`.
+pub fn lazy_ranges(capabilities: &ClientCapabilities) -> bool {
+    capabilities
+        .workspace
+        .as_ref()
+        .and_then(|w| w.symbol.as_ref())
+        .and_then(|s| s.resolve_support.as_ref())
+        .is_some_and(|r| r.properties.iter().any(|p| p == "location.range"))
+}
+
+/// Where the symbol is defined: a label's definition, the last one if repeated as that is
+/// where jumps go, or a variable's first `store`.
+fn definition(index: &DocumentIndex, kind: SymbolKind, name: &str) -> Option<Range> {
+    let named = |(site, range): &(String, Range)| (site == name).then_some(*range);
+    match kind {
+        SymbolKind::Label => index.labels.iter().rev().find_map(named),
+        SymbolKind::Variable => index.stores.iter().find_map(named),
+    }
+}
+
+/// Labels and variables of every tracked document whose name contains `query`, ignoring
+/// case, in one batch per document that has any. Unless `lazy`, each comes with its full
+/// location; otherwise only with its document, and `resolve` adds the rest.
+pub fn symbols(query: &str, lazy: bool) -> Vec<Vec<WorkspaceSymbol>> {
+    let query = query.to_lowercase();
+    let mut batches = Vec::new();
+    for (uri, document) in DOCUMENTS.lock().unwrap().iter() {
+        let index = &document.index;
+        let mut symbols = Vec::new();
+        let mut seen = HashSet::new();
+        let names = index
+            .labels
+            .iter()
+            .map(|(name, _)| (SymbolKind::Label, name))
+            .chain(
+                index
+                    .stores
+                    .iter()
+                    .map(|(name, _)| (SymbolKind::Variable, name)),
+            );
+        for (kind, name) in names {
+            if !name.to_lowercase().contains(&query) || !seen.insert((kind, name)) {
+                continue;
+            }
+            symbols.push(WorkspaceSymbol {
+                name: name.clone(),
+                kind: match kind {
+                    SymbolKind::Label => lsp_types::SymbolKind::FIELD,
+                    SymbolKind::Variable => lsp_types::SymbolKind::VARIABLE,
+                },
+                tags: None,
+                container_name: None,
+                location: OneOf::Right(WorkspaceLocation { uri: uri.clone() }),
+                data: serde_json::to_value(SymbolData { kind }).ok(),
+            });
+        }
+        if !symbols.is_empty() {
+            batches.push(symbols);
+        }
+    }
+    // resolving looks the document up again, so not while the documents are locked
+    if !lazy {
+        for symbols in &mut batches {
+            *symbols = std::mem::take(symbols).into_iter().map(resolve).collect();
+        }
+    }
+    batches
+}
+
+/// Fills in the symbol's range and, for a variable, the label whose section first stores it
+/// as its container.
+pub fn resolve(mut symbol: WorkspaceSymbol) -> WorkspaceSymbol {
+    let uri: Url = match &symbol.location {
+        OneOf::Left(location) => location.uri.clone(),
+        OneOf::Right(location) => location.uri.clone(),
+    };
+    let Some(data) = symbol
+        .data
+        .clone()
+        .and_then(|data| serde_json::from_value::<SymbolData>(data).ok())
+    else {
+        return symbol;
+    };
+    let Some(document) = document::get(&uri) else {
+        return symbol;
+    };
+    let Some(range) = definition(&document.index, data.kind, &symbol.name) else {
+        return symbol;
+    };
+    if data.kind == SymbolKind::Variable {
+        symbol.container_name = document
+            .index
+            .labels
+            .iter()
+            .rfind(|(_, label)| label.start.line < range.start.line)
+            .map(|(name, _)| name.clone());
+    }
+    symbol.location = OneOf::Left(Location {
+        uri,
+        range: position::range_to_lsp(&document.text, range),
+    });
+    symbol
+}